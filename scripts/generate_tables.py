@@ -175,7 +175,7 @@ def disasm_cb(opcode):
     return CB_CODES[opcode]['name'] if opcode in CB_CODES else 'Missing'
 
 if __name__ == "__main__":
-    for src_file in glob.glob('src/jeebie/instr/*.rs'):
+    for src_file in sorted(glob.glob('src/jeebie/instr/*.rs')):
         process_opcode_file(src_file)
 
     with open('src/jeebie/instr/opcodes.rs', 'w') as out_file:
@@ -0,0 +1,110 @@
+use jeebie::utils::is_set;
+
+/// The volume envelope of the square and noise channels, controlled by NRx2:
+///
+///     Bit 7-4 - Initial volume
+///     Bit 3   - Direction (0=decrease, 1=increase)
+///     Bit 2-0 - Period, in 64 Hz clocks (0=stopped)
+///
+/// The volume is reloaded on trigger and then moves by 1 every period, between 0 and 15.
+pub struct Envelope {
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope {
+            register: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+
+    /// Writes NRx2, the new values are only used from the next trigger.
+    pub fn write(&mut self, data: u8) {
+        self.register = data;
+    }
+
+    /// The DAC of the channel is on when any of the upper 5 bits of NRx2 is set.
+    pub fn dac_enabled(&self) -> bool {
+        self.register & 0xF8 != 0
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    fn period(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    /// Called when the channel is triggered, restarts from the initial volume.
+    pub fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.period();
+    }
+
+    /// Clocks the envelope, at 64 Hz.
+    pub fn clock(&mut self) {
+        if self.period() == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer > 0 {
+            return;
+        }
+
+        self.timer = self.period();
+
+        if is_set(self.register, 3) && self.volume < 15 {
+            self.volume += 1;
+        } else if !is_set(self.register, 3) && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
+
+#[test]
+fn envelope() {
+    let mut envelope = Envelope::new();
+    assert!(!envelope.dac_enabled());
+
+    // volume 2, decreasing every 2 clocks
+    envelope.write(0x22);
+    assert!(envelope.dac_enabled());
+    envelope.trigger();
+    assert_eq!(2, envelope.volume());
+
+    envelope.clock();
+    assert_eq!(2, envelope.volume());
+    envelope.clock();
+    assert_eq!(1, envelope.volume());
+    for _ in 0..4 {
+        envelope.clock();
+    }
+    assert_eq!(0, envelope.volume());
+
+    // volume 14, increasing every clock
+    envelope.write(0xE9);
+    envelope.trigger();
+    for _ in 0..4 {
+        envelope.clock();
+    }
+    assert_eq!(15, envelope.volume());
+
+    // period 0 stops the envelope, increasing with volume 0 keeps the DAC on
+    envelope.write(0x08);
+    assert!(envelope.dac_enabled());
+    envelope.trigger();
+    envelope.clock();
+    assert_eq!(0, envelope.volume());
+}
@@ -0,0 +1,66 @@
+/// Turns a channel off after a given time, if enabled by bit 6 of NRx4.
+/// It's clocked at 256 Hz by the frame sequencer.
+#[derive(Clone, Copy)]
+pub struct LengthCounter {
+    // the length loaded by a trigger when the counter is 0, 64 or 256 depending on the channel.
+    max: u16,
+    counter: u16,
+    pub enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> LengthCounter {
+        LengthCounter {
+            max: max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    /// Loads the length written to NRx1, the channel runs for `max - length` clocks.
+    pub fn load(&mut self, length: u16) {
+        self.counter = self.max - length;
+    }
+
+    /// Called when the channel is triggered, an expired counter starts again from the maximum.
+    pub fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+
+    /// Called when the APU is turned off. On DMG the counter keeps its value,
+    /// only the enable bit of NRx4 is cleared.
+    pub fn power_off(&mut self) {
+        self.enabled = false;
+    }
+
+    /// Clocks the counter, returns true when it expires and the channel has to be turned off.
+    pub fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+
+        self.counter -= 1;
+        self.counter == 0
+    }
+}
+
+#[test]
+fn length_counter() {
+    let mut length = LengthCounter::new(64);
+    length.load(62);
+    assert!(!length.clock());
+
+    length.enabled = true;
+    assert!(!length.clock());
+    assert!(length.clock());
+    // stays expired until the next trigger
+    assert!(!length.clock());
+
+    length.trigger();
+    for _ in 0..63 {
+        assert!(!length.clock());
+    }
+    assert!(length.clock());
+}
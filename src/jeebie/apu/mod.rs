@@ -0,0 +1,409 @@
+//! The Audio Processing Unit (APU), mapped to the sound registers (0xFF10 to 0xFF26)
+//! and the wave RAM (0xFF30 to 0xFF3F).
+//!
+//! The APU has four channels: two square waves (the first one with a frequency sweep),
+//! a wave channel playing 4-bit samples from the wave RAM and a noise channel.
+//! Their length counters, volume envelopes and sweep are clocked by the frame sequencer,
+//! which is driven by the DIV register of the timer at 512 Hz.
+pub mod envelope;
+pub mod length;
+pub mod square;
+pub mod wave;
+pub mod noise;
+pub mod resampler;
+
+use jeebie::utils::is_set;
+use self::square::SquareChannel;
+use self::wave::WaveChannel;
+use self::noise::NoiseChannel;
+use self::resampler::Resampler;
+
+/// Bits that always read as 1 in each register from NR10 (0xFF10) to NR52 (0xFF26),
+/// including the unused bits, write-only registers and the unmapped addresses.
+const READ_MASKS: [u8; 23] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+    0x00, 0x00, 0x70,             // NR50-NR52
+];
+
+pub struct APU {
+    // NR52 bit 7, while off all registers are cleared and can't be written.
+    enabled: bool,
+    square1: SquareChannel,
+    square2: SquareChannel,
+    wave: WaveChannel,
+    noise: NoiseChannel,
+    // NR50, master volume of each side (and VIN, unused).
+    nr50: u8,
+    // NR51, the channels sent to each side.
+    nr51: u8,
+    // next step of the frame sequencer, and the last value of the DIV bit driving it.
+    frame_step: u8,
+    div_bit: bool,
+    // converts the output to samples at the host rate, set by `set_sample_rate`.
+    resampler: Option<Resampler>,
+}
+
+impl APU {
+    pub fn new() -> APU {
+        APU {
+            enabled: false,
+            square1: SquareChannel::with_sweep(),
+            square2: SquareChannel::new(),
+            wave: WaveChannel::new(),
+            noise: NoiseChannel::new(),
+            nr50: 0,
+            nr51: 0,
+            frame_step: 0,
+            div_bit: false,
+            resampler: None,
+        }
+    }
+
+    /// Starts producing samples at `sample_rate` Hz, to be read with `drain_samples`.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.resampler = Some(Resampler::new(sample_rate));
+    }
+
+    /// Changes the rate of the samples by `ratio`, see `Resampler::set_rate_adjustment`.
+    pub fn set_rate_adjustment(&mut self, ratio: f64) {
+        if let Some(ref mut resampler) = self.resampler {
+            resampler.set_rate_adjustment(ratio);
+        }
+    }
+
+    /// Moves the samples produced so far to `out`, as interleaved left and right values.
+    /// Nothing is produced until a sample rate is set.
+    pub fn drain_samples(&mut self, out: &mut Vec<f32>) {
+        if let Some(ref mut resampler) = self.resampler {
+            resampler.drain(out);
+        }
+    }
+
+    /// Emulates the APU for `delta` clock cycles.
+    /// `div` is the current value of the DIV register, the frame sequencer is clocked when
+    /// its bit 4 goes from 1 to 0, so writes to DIV can clock it too.
+    pub fn emulate(&mut self, delta: u32, div: u8) {
+        let div_bit = is_set(div, 4);
+        if self.enabled && self.div_bit && !div_bit {
+            self.step_frame_sequencer();
+        }
+        self.div_bit = div_bit;
+
+        if !self.enabled {
+            // the output is silent, but samples are still produced
+            if let Some(ref mut resampler) = self.resampler {
+                resampler.push(0.0, 0.0, delta);
+            }
+            return;
+        }
+
+        for _ in 0..delta {
+            self.square1.tick();
+            self.square2.tick();
+            self.wave.tick();
+            self.noise.tick();
+
+            let (left, right) = self.output();
+            if let Some(ref mut resampler) = self.resampler {
+                resampler.push(left, right, 1);
+            }
+        }
+    }
+
+    /// Runs a step of the frame sequencer. Out of its 8 steps, the length counters are clocked
+    /// on even steps (256 Hz), the sweep on steps 2 and 6 (128 Hz) and the envelopes on step 7 (64 Hz).
+    fn step_frame_sequencer(&mut self) {
+        if self.frame_step % 2 == 0 {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.square1.clock_sweep();
+        }
+
+        if self.frame_step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+
+        self.frame_step = (self.frame_step + 1) & 0x07;
+    }
+
+    /// Returns the current output of the left and right side, between 0 and 1.
+    /// Each side is the average of the channels enabled for it in NR51, scaled by its volume in NR50.
+    pub fn output(&self) -> (f32, f32) {
+        if !self.enabled {
+            return (0.0, 0.0);
+        }
+
+        let channels = [
+            self.square1.output(),
+            self.square2.output(),
+            self.wave.output(),
+            self.noise.output(),
+        ];
+
+        let (mut left, mut right) = (0.0, 0.0);
+        for (i, &output) in channels.iter().enumerate() {
+            let output = output as f32 / 15.0;
+            if is_set(self.nr51, i + 4) {
+                left += output;
+            }
+            if is_set(self.nr51, i) {
+                right += output;
+            }
+        }
+
+        let left_volume = ((self.nr50 >> 4) & 0x07) as f32 + 1.0;
+        let right_volume = (self.nr50 & 0x07) as f32 + 1.0;
+
+        (left / 4.0 * left_volume / 8.0, right / 4.0 * right_volume / 8.0)
+    }
+
+    pub fn read_register(&self, addr: usize) -> u8 {
+        let value = match addr {
+            0xFF10..=0xFF14 => self.square1.read(addr - 0xFF10),
+            0xFF15..=0xFF19 => self.square2.read(addr - 0xFF15),
+            0xFF1A..=0xFF1E => self.wave.read(addr - 0xFF1A),
+            0xFF1F..=0xFF23 => self.noise.read(addr - 0xFF1F),
+            0xFF24 => self.nr50,
+            0xFF25 => self.nr51,
+            0xFF26 => {
+                (if self.enabled { 0x80 } else { 0 }) |
+                (if self.noise.enabled() { 0x08 } else { 0 }) |
+                (if self.wave.enabled() { 0x04 } else { 0 }) |
+                (if self.square2.enabled() { 0x02 } else { 0 }) |
+                (if self.square1.enabled() { 0x01 } else { 0 })
+            },
+            0xFF27..=0xFF2F => return 0xFF,
+            0xFF30..=0xFF3F => return self.wave.read_ram(addr - 0xFF30),
+            _ => panic!("Attempted APU register access with addr {:4x}", addr),
+        };
+
+        value | READ_MASKS[addr - 0xFF10]
+    }
+
+    pub fn write_register(&mut self, addr: usize, data: u8) {
+        // the wave RAM and NR52 are always writable
+        match addr {
+            0xFF26 => return self.write_nr52(data),
+            0xFF30..=0xFF3F => return self.wave.write_ram(addr - 0xFF30, data),
+            _ => {},
+        }
+
+        // while off, only the length counters can be written (on DMG)
+        if !self.enabled {
+            match addr {
+                0xFF11 => self.square1.write_length(data),
+                0xFF16 => self.square2.write_length(data),
+                0xFF1B => self.wave.write_length(data),
+                0xFF20 => self.noise.write_length(data),
+                _ => {},
+            }
+            return;
+        }
+
+        match addr {
+            0xFF10..=0xFF14 => self.square1.write(addr - 0xFF10, data),
+            0xFF15..=0xFF19 => self.square2.write(addr - 0xFF15, data),
+            0xFF1A..=0xFF1E => self.wave.write(addr - 0xFF1A, data),
+            0xFF1F..=0xFF23 => self.noise.write(addr - 0xFF1F, data),
+            0xFF24 => self.nr50 = data,
+            0xFF25 => self.nr51 = data,
+            0xFF27..=0xFF2F => {},
+            _ => panic!("Attempted APU register write with addr {:4x}", addr),
+        };
+    }
+
+    /// Writes NR52, only bit 7 (power) is writable, the channel bits are read-only.
+    /// Turning the APU off clears all the registers except the wave RAM and the length counters,
+    /// turning it on restarts the frame sequencer.
+    fn write_nr52(&mut self, data: u8) {
+        let enabled = is_set(data, 7);
+
+        if self.enabled && !enabled {
+            self.square1.power_off();
+            self.square2.power_off();
+            self.wave.power_off();
+            self.noise.power_off();
+            self.nr50 = 0;
+            self.nr51 = 0;
+        } else if !self.enabled && enabled {
+            self.frame_step = 0;
+        }
+
+        self.enabled = enabled;
+    }
+}
+
+/// Clocks the frame sequencer `steps` times, through falling edges of DIV bit 4.
+#[cfg(test)]
+fn clock_frame_sequencer(apu: &mut APU, steps: u32) {
+    for _ in 0..steps {
+        apu.emulate(0, 0x10);
+        apu.emulate(0, 0x00);
+    }
+}
+
+#[test]
+fn read_masks() {
+    let mut apu = APU::new();
+    apu.write_register(0xFF26, 0x80);
+
+    for addr in 0xFF10..0xFF26 {
+        apu.write_register(addr, 0x00);
+    }
+    for (i, &mask) in READ_MASKS[..0x16].iter().enumerate() {
+        assert_eq!(mask, apu.read_register(0xFF10 + i));
+    }
+    assert_eq!(0xF0, apu.read_register(0xFF26));
+    assert_eq!(0xFF, apu.read_register(0xFF27));
+
+    apu.write_register(0xFF11, 0xFF);
+    apu.write_register(0xFF24, 0x77);
+    assert_eq!(0xFF, apu.read_register(0xFF11));
+    assert_eq!(0x77, apu.read_register(0xFF24));
+}
+
+#[test]
+fn power_off_clears_registers() {
+    let mut apu = APU::new();
+    apu.write_register(0xFF26, 0x80);
+    apu.write_register(0xFF12, 0xF0);
+    apu.write_register(0xFF14, 0x80);
+    apu.write_register(0xFF25, 0xFF);
+    apu.write_register(0xFF30, 0x42);
+    assert_eq!(0xF1, apu.read_register(0xFF26));
+
+    apu.write_register(0xFF26, 0x00);
+    assert_eq!(0x70, apu.read_register(0xFF26));
+    assert_eq!(0x00, apu.read_register(0xFF12));
+    assert_eq!(0x00, apu.read_register(0xFF25));
+    // the wave RAM is kept
+    assert_eq!(0x42, apu.read_register(0xFF30));
+
+    // writes are ignored while off
+    apu.write_register(0xFF12, 0xF0);
+    assert_eq!(0x00, apu.read_register(0xFF12));
+    apu.write_register(0xFF26, 0x80);
+    assert_eq!(0x00, apu.read_register(0xFF12));
+}
+
+#[test]
+fn power_off_keeps_length_counters() {
+    let mut apu = APU::new();
+    apu.write_register(0xFF26, 0x80);
+
+    // channel 1 length 62 (2 clocks), loaded before turning the APU off
+    apu.write_register(0xFF11, 0x3E);
+    apu.write_register(0xFF26, 0x00);
+    // channel 2 length 62, loaded while off
+    apu.write_register(0xFF16, 0x3E);
+    apu.write_register(0xFF26, 0x80);
+
+    apu.write_register(0xFF12, 0xF0);
+    apu.write_register(0xFF14, 0xC0);
+    apu.write_register(0xFF17, 0xF0);
+    apu.write_register(0xFF19, 0xC0);
+    assert_eq!(0xF3, apu.read_register(0xFF26));
+
+    clock_frame_sequencer(&mut apu, 2);
+    assert_eq!(0xF3, apu.read_register(0xFF26));
+    clock_frame_sequencer(&mut apu, 1);
+    assert_eq!(0xF0, apu.read_register(0xFF26));
+}
+
+#[test]
+fn length_counter_turns_channel_off() {
+    let mut apu = APU::new();
+    apu.write_register(0xFF26, 0x80);
+
+    // length 62 (2 clocks) with length enabled
+    apu.write_register(0xFF17, 0xF0);
+    apu.write_register(0xFF16, 0x3E);
+    apu.write_register(0xFF19, 0xC0);
+    assert_eq!(0xF2, apu.read_register(0xFF26));
+
+    // the length counter is clocked on even steps
+    clock_frame_sequencer(&mut apu, 2);
+    assert_eq!(0xF2, apu.read_register(0xFF26));
+    clock_frame_sequencer(&mut apu, 1);
+    assert_eq!(0xF0, apu.read_register(0xFF26));
+}
+
+#[test]
+fn envelope_clocked_at_64hz() {
+    let mut apu = APU::new();
+    apu.write_register(0xFF26, 0x80);
+    apu.write_register(0xFF25, 0x22);
+    apu.write_register(0xFF24, 0x77);
+
+    // channel 2 at volume 15 decreasing every clock, 75% duty and frequency 0
+    apu.write_register(0xFF16, 0xC0);
+    apu.write_register(0xFF17, 0xF1);
+    apu.write_register(0xFF19, 0x80);
+    // move to the first high step of the waveform
+    apu.emulate(8192, 0x00);
+
+    let volume = |v: u8| v as f32 / 15.0 / 4.0 * 8.0 / 8.0;
+    assert_eq!((volume(15), volume(15)), apu.output());
+
+    clock_frame_sequencer(&mut apu, 7);
+    assert_eq!((volume(15), volume(15)), apu.output());
+    clock_frame_sequencer(&mut apu, 1);
+    assert_eq!((volume(14), volume(14)), apu.output());
+    clock_frame_sequencer(&mut apu, 8);
+    assert_eq!((volume(13), volume(13)), apu.output());
+}
+
+#[test]
+fn stereo_output() {
+    let mut apu = APU::new();
+    apu.write_register(0xFF26, 0x80);
+
+    // wave channel playing a constant 15
+    for addr in 0xFF30..0xFF40 {
+        apu.write_register(addr, 0xFF);
+    }
+    apu.write_register(0xFF1A, 0x80);
+    apu.write_register(0xFF1C, 0x20);
+    apu.write_register(0xFF1E, 0x80);
+
+    // only on the left side, at full volume
+    apu.write_register(0xFF25, 0x40);
+    apu.write_register(0xFF24, 0x70);
+    assert_eq!((0.25, 0.0), apu.output());
+
+    // both sides, the right one at the lowest volume
+    apu.write_register(0xFF25, 0x44);
+    assert_eq!((0.25, 0.25 / 8.0), apu.output());
+
+    apu.write_register(0xFF26, 0x00);
+    assert_eq!((0.0, 0.0), apu.output());
+}
+
+#[test]
+fn samples_at_host_rate() {
+    let mut apu = APU::new();
+    let mut samples = vec![];
+
+    // no samples until a rate is set
+    apu.emulate(70224, 0x00);
+    apu.drain_samples(&mut samples);
+    assert!(samples.is_empty());
+
+    // about 800 stereo samples per frame at 48 kHz, even with the APU off
+    apu.set_sample_rate(48000);
+    apu.emulate(70224, 0x00);
+    apu.write_register(0xFF26, 0x80);
+    apu.emulate(70224, 0x00);
+    apu.drain_samples(&mut samples);
+    assert_eq!(1607, samples.len() / 2);
+}
@@ -0,0 +1,175 @@
+use jeebie::utils::is_set;
+use super::envelope::Envelope;
+use super::length::LengthCounter;
+
+/// The noise channel (channel 4), registers NR41-NR44:
+///
+///     NR41 - Bit 5-0 length
+///     NR42 - Volume envelope
+///     NR43 - Bit 7-4 clock shift, bit 3 width (0=15 bits, 1=7 bits), bit 2-0 divisor code
+///     NR44 - Bit 7 trigger, bit 6 length enable
+///
+/// The output comes from a linear feedback shift register (LFSR), shifted every
+/// `divisor << shift` cycles. In 7 bits mode the sequence is shorter and sounds more regular.
+pub struct NoiseChannel {
+    enabled: bool,
+    length: LengthCounter,
+    envelope: Envelope,
+    register: u8,
+    lfsr: u16,
+    timer: u32,
+}
+
+impl NoiseChannel {
+    pub fn new() -> NoiseChannel {
+        NoiseChannel {
+            enabled: false,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            register: 0,
+            lfsr: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn period(&self) -> u32 {
+        let divisor = match self.register & 0x07 {
+            0 => 8,
+            code => code as u32 * 16,
+        };
+
+        divisor << (self.register >> 4)
+    }
+
+    /// Reads register NR40-NR44 (`reg` is 0 to 4, NR40 doesn't exist), write-only bits read as 0.
+    pub fn read(&self, reg: usize) -> u8 {
+        match reg {
+            0 | 1 => 0,
+            2 => self.envelope.read(),
+            3 => self.register,
+            4 => if self.length.enabled { 0x40 } else { 0 },
+            _ => panic!("Attempted noise channel register access with index {}", reg),
+        }
+    }
+
+    /// Writes register NR40-NR44 (`reg` is 0 to 4, NR40 doesn't exist).
+    pub fn write(&mut self, reg: usize, data: u8) {
+        match reg {
+            0 => {},
+            1 => self.write_length(data),
+            2 => {
+                self.envelope.write(data);
+                // turning the DAC off also turns the channel off
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            },
+            3 => self.register = data,
+            4 => {
+                self.length.enabled = is_set(data, 6);
+
+                if is_set(data, 7) {
+                    self.trigger();
+                }
+            },
+            _ => panic!("Attempted noise channel register write with index {}", reg),
+        }
+    }
+
+    /// Writes the length, NR41.
+    pub fn write_length(&mut self, data: u8) {
+        self.length.load((data & 0x3F) as u16);
+    }
+
+    /// Clears the registers when the APU is turned off, the length counter is kept.
+    pub fn power_off(&mut self) {
+        *self = NoiseChannel {
+            length: self.length,
+            ..NoiseChannel::new()
+        };
+        self.length.power_off();
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.envelope.trigger();
+        self.timer = self.period();
+        self.lfsr = 0x7FFF;
+    }
+
+    /// Advances the channel by one cycle.
+    pub fn tick(&mut self) {
+        self.timer = self.timer.saturating_sub(1);
+
+        if self.timer > 0 {
+            return;
+        }
+
+        self.timer = self.period();
+
+        // the XOR of the two low bits is shifted in from bit 14, and copied to bit 6 in 7 bits mode
+        let bit = (self.lfsr ^ (self.lfsr >> 1)) & 0x01;
+        self.lfsr = (self.lfsr >> 1) | (bit << 14);
+        if is_set(self.register, 3) {
+            self.lfsr = (self.lfsr & !0x40) | (bit << 6);
+        }
+    }
+
+    /// Clocks the length counter, at 256 Hz.
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Clocks the volume envelope, at 64 Hz.
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Returns the current output of the channel, from 0 to 15.
+    /// The channel is high when bit 0 of the LFSR is 0.
+    pub fn output(&self) -> u8 {
+        if self.enabled && self.lfsr & 0x01 == 0 {
+            self.envelope.volume()
+        } else {
+            0
+        }
+    }
+}
+
+#[test]
+fn noise_lfsr() {
+    let mut channel = NoiseChannel::new();
+    // volume 15, divisor 8 with no shift
+    channel.write(2, 0xF0);
+    channel.write(3, 0x00);
+    channel.write(4, 0x80);
+    assert!(channel.enabled());
+    assert_eq!(0x7FFF, channel.lfsr);
+    assert_eq!(0, channel.output());
+
+    for _ in 0..8 {
+        channel.tick();
+    }
+    // bits 0 and 1 are both set, a 0 is shifted in
+    assert_eq!(0x3FFF, channel.lfsr);
+
+    // in 7 bits mode the sequence repeats every 127 shifts
+    channel.write(3, 0x08);
+    channel.write(4, 0x80);
+    let mut sequence = vec![];
+    for _ in 0..254 {
+        for _ in 0..8 {
+            channel.tick();
+        }
+        sequence.push(channel.output());
+    }
+    assert_eq!(sequence[0..127], sequence[127..254]);
+    assert!(sequence.contains(&15));
+}
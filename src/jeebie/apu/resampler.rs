@@ -0,0 +1,222 @@
+use std::f64::consts::PI;
+
+/// The APU clock, in cycles per second.
+const CLOCK_RATE: f64 = 4_194_304.0;
+/// The output of the APU is first averaged down to this multiple of the host rate,
+/// then low-pass filtered and decimated to the host rate.
+const OVERSAMPLING: usize = 4;
+/// Length of the low-pass filter.
+const TAPS: usize = 64;
+/// Cutoff of the low-pass filter, as a fraction of the host rate.
+const CUTOFF: f64 = 0.4;
+/// Cutoff of the high-pass filter removing the DC offset, in Hz.
+const DC_CUTOFF: f64 = 20.0;
+
+/// Converts the output of the APU, one stereo value per clock cycle, to samples at the host rate.
+///
+/// Averaging the input over each intermediate sample removes most of the frequencies that would
+/// alias, a windowed sinc filter removes the rest before dropping to the host rate. The DC offset
+/// is then removed, like the capacitors on the audio output of the hardware do.
+pub struct Resampler {
+    sample_rate: u32,
+    // input cycles per intermediate sample, the length of the current one and the cycles left in it.
+    period: f64,
+    length: f64,
+    remaining: f64,
+    // sum of the input in the current intermediate sample.
+    sum: (f32, f32),
+    // the last TAPS intermediate samples, `position` is the oldest one.
+    history: [(f32, f32); TAPS],
+    position: usize,
+    // intermediate samples since the last output sample.
+    phase: usize,
+    coefficients: [f32; TAPS],
+    // state of the DC filter, the last input and output.
+    dc_factor: f32,
+    dc_input: (f32, f32),
+    dc_output: (f32, f32),
+    // interleaved stereo samples waiting to be read.
+    samples: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(sample_rate: u32) -> Resampler {
+        let period = Resampler::intermediate_period(sample_rate, 1.0);
+
+        Resampler {
+            sample_rate: sample_rate,
+            period: period,
+            length: period,
+            remaining: period,
+            sum: (0.0, 0.0),
+            history: [(0.0, 0.0); TAPS],
+            position: 0,
+            phase: 0,
+            coefficients: Resampler::low_pass(),
+            dc_factor: (1.0 - 2.0 * PI * DC_CUTOFF / sample_rate as f64) as f32,
+            dc_input: (0.0, 0.0),
+            dc_output: (0.0, 0.0),
+            samples: vec![],
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Input cycles per intermediate sample, for a host rate multiplied by `ratio`.
+    fn intermediate_period(sample_rate: u32, ratio: f64) -> f64 {
+        CLOCK_RATE / (sample_rate as f64 * ratio * OVERSAMPLING as f64)
+    }
+
+    /// Computes a Blackman windowed sinc low-pass filter, with unity gain.
+    fn low_pass() -> [f32; TAPS] {
+        // cutoff relative to the intermediate rate
+        let cutoff = CUTOFF / OVERSAMPLING as f64;
+        let center = (TAPS - 1) as f64 / 2.0;
+        let mut coefficients = [0.0; TAPS];
+        let mut sum = 0.0;
+
+        for (i, coefficient) in coefficients.iter_mut().enumerate() {
+            let x = i as f64 - center;
+            let sinc = if x == 0.0 { 1.0 } else { (2.0 * PI * cutoff * x).sin() / (2.0 * PI * cutoff * x) };
+            let phase = 2.0 * PI * i as f64 / (TAPS - 1) as f64;
+            let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+
+            *coefficient = sinc * window;
+            sum += *coefficient;
+        }
+
+        let mut result = [0.0; TAPS];
+        for (out, coefficient) in result.iter_mut().zip(coefficients.iter()) {
+            *out = (coefficient / sum) as f32;
+        }
+        result
+    }
+
+    /// Produces samples a bit faster (`ratio` > 1) or slower (`ratio` < 1) than the host rate,
+    /// so that the frontend can keep its buffer from running empty or growing forever.
+    pub fn set_rate_adjustment(&mut self, ratio: f64) {
+        self.period = Resampler::intermediate_period(self.sample_rate, ratio);
+    }
+
+    /// Adds `cycles` cycles of the APU output.
+    pub fn push(&mut self, left: f32, right: f32, cycles: u32) {
+        let mut cycles = cycles as f64;
+
+        while cycles > 0.0 {
+            let step = cycles.min(self.remaining);
+            self.sum.0 += left * step as f32;
+            self.sum.1 += right * step as f32;
+            self.remaining -= step;
+            cycles -= step;
+
+            if self.remaining <= 0.0 {
+                let length = self.length as f32;
+                let sample = (self.sum.0 / length, self.sum.1 / length);
+                self.sum = (0.0, 0.0);
+                // the rate adjustment only applies from the next intermediate sample
+                self.length = self.period;
+                self.remaining = self.period;
+                self.push_intermediate(sample);
+            }
+        }
+    }
+
+    fn push_intermediate(&mut self, sample: (f32, f32)) {
+        self.history[self.position] = sample;
+        self.position = (self.position + 1) % TAPS;
+
+        self.phase += 1;
+        if self.phase < OVERSAMPLING {
+            return;
+        }
+        self.phase = 0;
+
+        let (mut left, mut right) = (0.0, 0.0);
+        for (i, &coefficient) in self.coefficients.iter().enumerate() {
+            let (l, r) = self.history[(self.position + i) % TAPS];
+            left += l * coefficient;
+            right += r * coefficient;
+        }
+
+        self.dc_output = (
+            left - self.dc_input.0 + self.dc_factor * self.dc_output.0,
+            right - self.dc_input.1 + self.dc_factor * self.dc_output.1,
+        );
+        self.dc_input = (left, right);
+
+        self.samples.push(self.dc_output.0.clamp(-1.0, 1.0));
+        self.samples.push(self.dc_output.1.clamp(-1.0, 1.0));
+    }
+
+    /// Moves the samples produced so far to `out`, as interleaved left and right values.
+    pub fn drain(&mut self, out: &mut Vec<f32>) {
+        out.extend(self.samples.drain(..));
+    }
+}
+
+/// Resamples a sine wave of `frequency` Hz and amplitude 0.5 for 100ms,
+/// returns the left output samples.
+#[cfg(test)]
+fn resample_sine(frequency: f64) -> Vec<f32> {
+    let mut resampler = Resampler::new(48000);
+    for cycle in 0..(CLOCK_RATE / 10.0) as u32 {
+        let value = 0.5 * (2.0 * PI * frequency * cycle as f64 / CLOCK_RATE).sin() as f32;
+        resampler.push(value, value, 1);
+    }
+
+    let mut samples = vec![];
+    resampler.drain(&mut samples);
+    samples.iter().step_by(2).cloned().collect()
+}
+
+#[test]
+fn resampler_rate() {
+    let mut samples = vec![];
+
+    let mut resampler = Resampler::new(48000);
+    resampler.push(0.0, 0.0, CLOCK_RATE as u32);
+    resampler.drain(&mut samples);
+    assert!((samples.len() as i32 / 2 - 48000).abs() <= 1);
+
+    let mut resampler = Resampler::new(44100);
+    resampler.set_rate_adjustment(1.005);
+    samples.clear();
+    resampler.push(0.0, 0.0, CLOCK_RATE as u32);
+    resampler.drain(&mut samples);
+    assert!((samples.len() as i32 / 2 - 44320).abs() <= 1);
+
+    // drained samples are not returned again
+    samples.clear();
+    resampler.drain(&mut samples);
+    assert!(samples.is_empty());
+}
+
+#[test]
+fn resampler_removes_dc() {
+    let mut resampler = Resampler::new(48000);
+    resampler.push(0.5, 0.5, CLOCK_RATE as u32);
+
+    let mut samples = vec![];
+    resampler.drain(&mut samples);
+    assert!(samples[samples.len() - 1].abs() < 0.001);
+}
+
+#[test]
+fn resampler_band_limited() {
+    // the filter delays the output by 31.5 intermediate samples, centered on the averaged input:
+    // 7 samples at the host rate
+    let samples = resample_sine(1000.0);
+    for (i, &sample) in samples.iter().enumerate().skip(1000) {
+        let t = (i as f64 - 7.0) / 48000.0;
+        let expected = 0.5 * (2.0 * PI * 1000.0 * t).sin() as f32;
+        assert!((sample - expected).abs() < 0.03, "sample {}: {} instead of {}", i, sample, expected);
+    }
+
+    // frequencies over half the host rate are removed
+    for &frequency in &[30000.0, 60000.0] {
+        let peak = resample_sine(frequency).iter().skip(1000).fold(0.0, |max: f32, sample| max.max(sample.abs()));
+        assert!(peak < 0.01);
+    }
+}
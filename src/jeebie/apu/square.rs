@@ -0,0 +1,320 @@
+use jeebie::utils::is_set;
+use super::envelope::Envelope;
+use super::length::LengthCounter;
+
+/// The waveforms selected by the duty bits of NRx1, 12.5%, 25%, 50% and 75%.
+/// Bit 7 is the first step of the waveform.
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// The frequency sweep of channel 1, controlled by NR10:
+///
+///     Bit 6-4 - Period, in 128 Hz clocks (0=stopped)
+///     Bit 3   - Direction (0=increase, 1=decrease)
+///     Bit 2-0 - Shift
+///
+/// Each period the frequency changes by `frequency >> shift`, the channel is turned off if the
+/// frequency goes over 2047.
+struct Sweep {
+    register: u8,
+    enabled: bool,
+    // copy of the frequency used for the calculations.
+    shadow: u16,
+    timer: u8,
+}
+
+impl Sweep {
+    fn new() -> Sweep {
+        Sweep {
+            register: 0,
+            enabled: false,
+            shadow: 0,
+            timer: 0,
+        }
+    }
+
+    /// The period in NR10, 0 means that the sweep is stopped.
+    fn pace(&self) -> u8 {
+        (self.register >> 4) & 0x07
+    }
+
+    /// The value reloaded in the timer, a period of 0 reloads it with 8.
+    fn period(&self) -> u8 {
+        match self.pace() {
+            0 => 8,
+            pace => pace,
+        }
+    }
+
+    fn shift(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    /// Returns the next frequency, a value above 2047 turns the channel off.
+    fn calculate(&self) -> u16 {
+        let delta = self.shadow >> self.shift();
+        if is_set(self.register, 3) {
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+}
+
+/// A square wave channel (channel 1 and 2), registers NR10-NR14 and NR21-NR24:
+///
+///     NRx0 - Sweep, channel 1 only
+///     NRx1 - Bit 7-6 duty, bit 5-0 length
+///     NRx2 - Volume envelope
+///     NRx3 - Frequency, low 8 bits
+///     NRx4 - Bit 7 trigger, bit 6 length enable, bit 2-0 frequency high bits
+///
+/// The waveform advances by one of its 8 steps every `(2048 - frequency) * 4` cycles.
+pub struct SquareChannel {
+    enabled: bool,
+    sweep: Option<Sweep>,
+    length: LengthCounter,
+    envelope: Envelope,
+    duty: u8,
+    duty_step: u8,
+    frequency: u16,
+    timer: u16,
+}
+
+impl SquareChannel {
+    /// Creates channel 1, with the frequency sweep.
+    pub fn with_sweep() -> SquareChannel {
+        SquareChannel {
+            sweep: Some(Sweep::new()),
+            ..SquareChannel::new()
+        }
+    }
+
+    /// Creates channel 2, without the frequency sweep.
+    pub fn new() -> SquareChannel {
+        SquareChannel {
+            enabled: false,
+            sweep: None,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            duty: 0,
+            duty_step: 0,
+            frequency: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn period(&self) -> u16 {
+        (2048 - self.frequency) * 4
+    }
+
+    /// Reads register NRx0-NRx4 (`reg` is 0 to 4), write-only bits read as 0.
+    pub fn read(&self, reg: usize) -> u8 {
+        match reg {
+            0 => self.sweep.as_ref().map_or(0, |sweep| sweep.register),
+            1 => self.duty << 6,
+            2 => self.envelope.read(),
+            3 => 0,
+            4 => if self.length.enabled { 0x40 } else { 0 },
+            _ => panic!("Attempted square channel register access with index {}", reg),
+        }
+    }
+
+    /// Writes register NRx0-NRx4 (`reg` is 0 to 4).
+    pub fn write(&mut self, reg: usize, data: u8) {
+        match reg {
+            0 => if let Some(ref mut sweep) = self.sweep {
+                sweep.register = data;
+            },
+            1 => {
+                self.duty = data >> 6;
+                self.length.load((data & 0x3F) as u16);
+            },
+            2 => {
+                self.envelope.write(data);
+                // turning the DAC off also turns the channel off
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            },
+            3 => self.frequency = (self.frequency & 0x700) | data as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | (((data & 0x07) as u16) << 8);
+                self.length.enabled = is_set(data, 6);
+
+                if is_set(data, 7) {
+                    self.trigger();
+                }
+            },
+            _ => panic!("Attempted square channel register write with index {}", reg),
+        }
+    }
+
+    /// Writes the length, without changing the duty. Used while the APU is off, where only
+    /// the length counters can be written.
+    pub fn write_length(&mut self, data: u8) {
+        self.length.load((data & 0x3F) as u16);
+    }
+
+    /// Clears the registers when the APU is turned off, the length counter is kept.
+    pub fn power_off(&mut self) {
+        let channel = if self.sweep.is_some() { SquareChannel::with_sweep() } else { SquareChannel::new() };
+        *self = SquareChannel {
+            length: self.length,
+            ..channel
+        };
+        self.length.power_off();
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        if let Some(ref mut sweep) = self.sweep {
+            sweep.shadow = self.frequency;
+            sweep.timer = sweep.period();
+            sweep.enabled = sweep.pace() != 0 || sweep.shift() != 0;
+
+            // the overflow check is done right away if there's a shift
+            if sweep.shift() != 0 && sweep.calculate() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Advances the channel by one cycle.
+    pub fn tick(&mut self) {
+        self.timer = self.timer.saturating_sub(1);
+
+        if self.timer == 0 {
+            self.timer = self.period();
+            self.duty_step = (self.duty_step + 1) & 0x07;
+        }
+    }
+
+    /// Clocks the length counter, at 256 Hz.
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Clocks the volume envelope, at 64 Hz.
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Clocks the frequency sweep, at 128 Hz. This does nothing on channel 2.
+    pub fn clock_sweep(&mut self) {
+        let sweep = match self.sweep {
+            Some(ref mut sweep) => sweep,
+            None => return,
+        };
+
+        if sweep.timer > 0 {
+            sweep.timer -= 1;
+        }
+        if sweep.timer > 0 {
+            return;
+        }
+
+        sweep.timer = sweep.period();
+        // a period of 0 only reloads the timer
+        if !sweep.enabled || sweep.pace() == 0 {
+            return;
+        }
+
+        let frequency = sweep.calculate();
+        if frequency > 2047 {
+            self.enabled = false;
+            return;
+        }
+
+        if sweep.shift() != 0 {
+            self.frequency = frequency;
+            sweep.shadow = frequency;
+
+            // the new frequency is checked again, without being used
+            if sweep.calculate() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Returns the current output of the channel, from 0 to 15.
+    pub fn output(&self) -> u8 {
+        let high = (DUTY_PATTERNS[self.duty as usize] >> (7 - self.duty_step)) & 0x01 == 1;
+
+        if self.enabled && high {
+            self.envelope.volume()
+        } else {
+            0
+        }
+    }
+}
+
+#[test]
+fn square_duty() {
+    let mut channel = SquareChannel::new();
+    // 50% duty, volume 15, frequency 2047 (a step every 4 cycles)
+    channel.write(1, 0x80);
+    channel.write(2, 0xF0);
+    channel.write(3, 0xFF);
+    channel.write(4, 0x87);
+    assert!(channel.enabled());
+
+    let mut waveform = vec![];
+    for _ in 0..8 {
+        for _ in 0..4 {
+            channel.tick();
+        }
+        waveform.push(channel.output());
+    }
+    // the waveform starts from step 1, step 0 is reached last
+    assert_eq!(vec![0, 0, 0, 0, 15, 15, 15, 15], waveform);
+}
+
+#[test]
+fn square_dac_off() {
+    let mut channel = SquareChannel::new();
+    channel.write(4, 0x80);
+    assert!(!channel.enabled());
+
+    channel.write(2, 0xF0);
+    channel.write(4, 0x80);
+    assert!(channel.enabled());
+
+    channel.write(2, 0x00);
+    assert!(!channel.enabled());
+}
+
+#[test]
+fn square_sweep() {
+    let mut channel = SquareChannel::with_sweep();
+    // period 1, increase, shift 1
+    channel.write(0, 0x11);
+    channel.write(2, 0xF0);
+    channel.write(3, 0x00);
+    channel.write(4, 0x82);
+    assert!(channel.enabled());
+
+    // 0x200 -> 0x300 -> 0x480 -> 0x6C0, and the next one overflows
+    channel.clock_sweep();
+    assert_eq!(0x300, channel.frequency);
+    channel.clock_sweep();
+    assert_eq!(0x480, channel.frequency);
+    channel.clock_sweep();
+    assert_eq!(0x6C0, channel.frequency);
+    // the second check sees the overflow of 0x6C0 + 0x360 before it's used
+    assert!(!channel.enabled());
+
+    // the overflow check is also done on trigger
+    channel.write(3, 0xFF);
+    channel.write(4, 0x87);
+    assert!(!channel.enabled());
+}
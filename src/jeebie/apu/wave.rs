@@ -0,0 +1,178 @@
+use jeebie::utils::is_set;
+use super::length::LengthCounter;
+
+/// The wave channel (channel 3), registers NR30-NR34:
+///
+///     NR30 - Bit 7 DAC power
+///     NR31 - Length
+///     NR32 - Bit 6-5 output level (0=mute, 1=100%, 2=50%, 3=25%)
+///     NR33 - Frequency, low 8 bits
+///     NR34 - Bit 7 trigger, bit 6 length enable, bit 2-0 frequency high bits
+///
+/// It plays the 32 4-bit samples of the wave RAM (0xFF30-0xFF3F), high nibble first,
+/// advancing by one sample every `(2048 - frequency) * 2` cycles.
+pub struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    length: LengthCounter,
+    output_level: u8,
+    frequency: u16,
+    timer: u16,
+    position: u8,
+    ram: [u8; 16],
+}
+
+impl WaveChannel {
+    pub fn new() -> WaveChannel {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            length: LengthCounter::new(256),
+            output_level: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            ram: [0; 16],
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn period(&self) -> u16 {
+        (2048 - self.frequency) * 2
+    }
+
+    /// Reads register NR30-NR34 (`reg` is 0 to 4), write-only bits read as 0.
+    pub fn read(&self, reg: usize) -> u8 {
+        match reg {
+            0 => if self.dac_enabled { 0x80 } else { 0 },
+            1 => 0,
+            2 => self.output_level << 5,
+            3 => 0,
+            4 => if self.length.enabled { 0x40 } else { 0 },
+            _ => panic!("Attempted wave channel register access with index {}", reg),
+        }
+    }
+
+    /// Writes register NR30-NR34 (`reg` is 0 to 4).
+    pub fn write(&mut self, reg: usize, data: u8) {
+        match reg {
+            0 => {
+                self.dac_enabled = is_set(data, 7);
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            },
+            1 => self.write_length(data),
+            2 => self.output_level = (data >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x700) | data as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | (((data & 0x07) as u16) << 8);
+                self.length.enabled = is_set(data, 6);
+
+                if is_set(data, 7) {
+                    self.trigger();
+                }
+            },
+            _ => panic!("Attempted wave channel register write with index {}", reg),
+        }
+    }
+
+    /// Writes the length, NR31.
+    pub fn write_length(&mut self, data: u8) {
+        self.length.load(data as u16);
+    }
+
+    /// Reads a byte of the wave RAM, `addr` is 0 to 15.
+    pub fn read_ram(&self, addr: usize) -> u8 {
+        self.ram[addr]
+    }
+
+    /// Writes a byte of the wave RAM, `addr` is 0 to 15.
+    pub fn write_ram(&mut self, addr: usize, data: u8) {
+        self.ram[addr] = data;
+    }
+
+    /// Clears the registers when the APU is turned off, the wave RAM and the length counter are kept.
+    pub fn power_off(&mut self) {
+        *self = WaveChannel {
+            length: self.length,
+            ram: self.ram,
+            ..WaveChannel::new()
+        };
+        self.length.power_off();
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger();
+        self.timer = self.period();
+        self.position = 0;
+    }
+
+    /// Advances the channel by one cycle.
+    pub fn tick(&mut self) {
+        self.timer = self.timer.saturating_sub(1);
+
+        if self.timer == 0 {
+            self.timer = self.period();
+            self.position = (self.position + 1) & 0x1F;
+        }
+    }
+
+    /// Clocks the length counter, at 256 Hz.
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Returns the current output of the channel, from 0 to 15.
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let byte = self.ram[self.position as usize / 2];
+        let sample = if self.position % 2 == 0 { byte >> 4 } else { byte & 0x0F };
+
+        match self.output_level {
+            0 => 0,
+            level => sample >> (level - 1),
+        }
+    }
+}
+
+#[test]
+fn wave_output() {
+    let mut channel = WaveChannel::new();
+    for i in 0..16 {
+        channel.write_ram(i, ((i * 2) << 4 | (i * 2 + 1)) as u8);
+    }
+    // 100% volume, frequency 2047 (a sample every 2 cycles)
+    channel.write(0, 0x80);
+    channel.write(2, 0x20);
+    channel.write(3, 0xFF);
+    channel.write(4, 0x87);
+    assert!(channel.enabled());
+
+    let mut samples = vec![];
+    for _ in 0..4 {
+        channel.tick();
+        channel.tick();
+        samples.push(channel.output());
+    }
+    // the first sample is only played after a full period
+    assert_eq!(vec![1, 2, 3, 4], samples);
+
+    // 25% volume
+    channel.write(2, 0x60);
+    assert_eq!(1, channel.output());
+    channel.write(2, 0x00);
+    assert_eq!(0, channel.output());
+
+    channel.write(0, 0x00);
+    assert!(!channel.enabled());
+}
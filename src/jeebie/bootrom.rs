@@ -1,3 +1,5 @@
+use std::str::FromStr;
+use std::fs;
 
 /// Bootrom for DMG (original gameboy) - from gearboy
 pub static DMG_BOOTROM: [u8; 256] = [0x31, 0xFE, 0xFF, 0xAF, 0x21, 0xFF, 0x9F, 0x32, 0xCB, 0x7C,
@@ -26,3 +28,137 @@ pub static DMG_BOOTROM: [u8; 256] = [0x31, 0xFE, 0xFF, 0xAF, 0x21, 0xFF, 0x9F, 0
                                      0x1A, 0x13, 0xBE, 0x00, 0x00, 0x23, 0x7D, 0xFE, 0x34, 0x20,
                                      0xF5, 0x06, 0x19, 0x78, 0x86, 0x23, 0x05, 0x20, 0xFB, 0x86,
                                      0x00, 0x00, 0x3E, 0x01, 0xE0, 0x50];
+
+/// A boot ROM image, mapped over the cartridge ROM until a write to 0xFF50 unmaps it.
+/// DMG, MGB and SGB boot ROMs are 256 bytes long and mapped at 0x000-0x0FF. CGB boot ROMs
+/// are 2304 bytes long and are also mapped at 0x200-0x8FF, leaving the cartridge header visible.
+pub struct BootRom {
+    data: Vec<u8>,
+}
+
+impl BootRom {
+    /// Returns the built-in DMG boot ROM.
+    pub fn dmg() -> BootRom {
+        BootRom { data: DMG_BOOTROM.to_vec() }
+    }
+
+    /// Loads a boot ROM image from the file at `path`.
+    pub fn new_with_path(path: &str) -> Result<BootRom, String> {
+        let data = fs::read(path).map_err(|e| format!("Could not read boot ROM {}: {}", path, e))?;
+        BootRom::new_with_vec(data)
+    }
+
+    /// Creates a boot ROM from an image, an error is returned if the size doesn't match
+    /// any of the known boot ROMs.
+    pub fn new_with_vec(data: Vec<u8>) -> Result<BootRom, String> {
+        match data.len() {
+            0x100 | 0x900 => Ok(BootRom { data: data }),
+            len => Err(format!("Invalid boot ROM size {} bytes, expected 256 or 2304", len)),
+        }
+    }
+
+    /// Returns true if `addr` is covered by the boot ROM while it's mapped.
+    pub fn is_mapped(&self, addr: u16) -> bool {
+        match addr {
+            0x000..=0x0FF => true,
+            0x200..=0x8FF => self.data.len() == 0x900,
+            _ => false,
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        self.data[addr as usize]
+    }
+}
+
+/// The Gameboy hardware revisions. Each model leaves the CPU and I/O registers
+/// in a slightly different state when its boot ROM hands control to the cartridge,
+/// some games read these values to detect which model they're running on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Model {
+    /// Early original Gameboy, only released in Japan.
+    DMG0,
+    /// Original Gameboy.
+    DMG,
+    /// Gameboy Pocket.
+    MGB,
+    /// Gameboy Color.
+    CGB,
+    /// Gameboy Advance, running Gameboy games.
+    AGB,
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "dmg0" => Ok(Model::DMG0),
+            "dmg" => Ok(Model::DMG),
+            "mgb" => Ok(Model::MGB),
+            "cgb" => Ok(Model::CGB),
+            "agb" => Ok(Model::AGB),
+            _ => Err(format!("Unknown model {}", name)),
+        }
+    }
+}
+
+impl Model {
+    /// Returns the value of the internal timer counter when the boot ROM is done, DIV is its upper byte.
+    pub fn post_boot_counter(&self) -> u16 {
+        match *self {
+            Model::DMG0 => 0x1830,
+            Model::DMG | Model::MGB => 0xABCC,
+            Model::CGB | Model::AGB => 0x1EA0,
+        }
+    }
+
+    /// Returns the I/O state left by the boot ROM that depends on the model.
+    pub fn post_boot_io(&self) -> PostBootIo {
+        match *self {
+            // LY=0x91, STAT=0x81
+            Model::DMG0 => PostBootIo { lcd_line: 145, lcd_cycles: 100, sc: 0x00, dma: 0xFF },
+            // LY=0x00, STAT=0x85, the last line of VBlank already reads as line 0
+            Model::DMG | Model::MGB => PostBootIo { lcd_line: 153, lcd_cycles: 100, sc: 0x00, dma: 0xFF },
+            // LY and STAT are not documented, the DMG values are used
+            Model::CGB | Model::AGB => PostBootIo { lcd_line: 153, lcd_cycles: 100, sc: 0x01, dma: 0x00 },
+        }
+    }
+}
+
+/// The I/O registers that differ between models when the boot ROM hands control to the cartridge.
+/// The others are the same on all models, DIV is given by `Model::post_boot_counter`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PostBootIo {
+    /// The VBlank line the LCD is in, and the cycles already spent in it.
+    pub lcd_line: u8,
+    pub lcd_cycles: u32,
+    /// SC, the CGB boot ROM leaves the internal clock selected.
+    pub sc: u8,
+    /// DMA, the last value written.
+    pub dma: u8,
+}
+
+#[test]
+fn boot_rom_sizes() {
+    assert!(BootRom::new_with_vec(vec![0; 0x100]).is_ok());
+    assert!(BootRom::new_with_vec(vec![0; 0x900]).is_ok());
+    assert!(BootRom::new_with_vec(vec![0; 0x200]).is_err());
+}
+
+#[test]
+fn boot_rom_mapping() {
+    let dmg = BootRom::dmg();
+    assert!(dmg.is_mapped(0x00FF));
+    assert!(!dmg.is_mapped(0x0100));
+    assert!(!dmg.is_mapped(0x0200));
+
+    // the CGB boot ROM leaves the cartridge header visible
+    let cgb = BootRom::new_with_vec(vec![0; 0x900]).unwrap();
+    assert!(cgb.is_mapped(0x00FF));
+    assert!(!cgb.is_mapped(0x0100));
+    assert!(!cgb.is_mapped(0x01FF));
+    assert!(cgb.is_mapped(0x0200));
+    assert!(cgb.is_mapped(0x08FF));
+    assert!(!cgb.is_mapped(0x0900));
+}
@@ -0,0 +1,394 @@
+use jeebie::utils::combine_as_u16;
+
+use super::CartridgeError;
+use super::licensee::{new_licensee_name, old_licensee_name};
+
+/// The header occupies 0x100-0x14F, so a valid ROM is at least this long.
+pub const HEADER_END: usize = 0x150;
+
+/// The Nintendo logo, as stored at 0x104-0x133 of every licensed cartridge.
+/// The boot ROM scrolls it on screen and locks up if it doesn't match its own copy.
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83,
+    0x00, 0x0C, 0x00, 0x0D, 0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E,
+    0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99, 0xBB, 0xBB, 0x67, 0x63,
+    0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+/// The hardware on the cartridge, as specified by the cartridge type byte (0x147).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CartridgeType {
+    RomOnly,
+    Mbc1,
+    Mbc1Ram,
+    Mbc1RamBattery,
+    Mbc2,
+    Mbc2Battery,
+    RomRam,
+    RomRamBattery,
+    Mmm01,
+    Mmm01Ram,
+    Mmm01RamBattery,
+    Mbc3TimerBattery,
+    Mbc3TimerRamBattery,
+    Mbc3,
+    Mbc3Ram,
+    Mbc3RamBattery,
+    Mbc5,
+    Mbc5Ram,
+    Mbc5RamBattery,
+    Mbc5Rumble,
+    Mbc5RumbleRam,
+    Mbc5RumbleRamBattery,
+    Mbc6,
+    Mbc7SensorRumbleRamBattery,
+    PocketCamera,
+    BandaiTama5,
+    HuC3,
+    HuC1RamBattery,
+}
+
+impl CartridgeType {
+    pub fn from_code(code: u8) -> Result<Self, CartridgeError> {
+        use self::CartridgeType::*;
+
+        let cartridge_type = match code {
+            0x00 => RomOnly,
+            0x01 => Mbc1,
+            0x02 => Mbc1Ram,
+            0x03 => Mbc1RamBattery,
+            0x05 => Mbc2,
+            0x06 => Mbc2Battery,
+            0x08 => RomRam,
+            0x09 => RomRamBattery,
+            0x0B => Mmm01,
+            0x0C => Mmm01Ram,
+            0x0D => Mmm01RamBattery,
+            0x0F => Mbc3TimerBattery,
+            0x10 => Mbc3TimerRamBattery,
+            0x11 => Mbc3,
+            0x12 => Mbc3Ram,
+            0x13 => Mbc3RamBattery,
+            0x19 => Mbc5,
+            0x1A => Mbc5Ram,
+            0x1B => Mbc5RamBattery,
+            0x1C => Mbc5Rumble,
+            0x1D => Mbc5RumbleRam,
+            0x1E => Mbc5RumbleRamBattery,
+            0x20 => Mbc6,
+            0x22 => Mbc7SensorRumbleRamBattery,
+            0xFC => PocketCamera,
+            0xFD => BandaiTama5,
+            0xFE => HuC3,
+            0xFF => HuC1RamBattery,
+            _ => return Err(CartridgeError::UnknownCartridgeType(code)),
+        };
+
+        Ok(cartridge_type)
+    }
+
+    /// Returns true if the cartridge has a battery to keep RAM (or the RTC) alive.
+    pub fn has_battery(&self) -> bool {
+        use self::CartridgeType::*;
+
+        match *self {
+            Mbc1RamBattery | Mbc2Battery | RomRamBattery | Mmm01RamBattery |
+            Mbc3TimerBattery | Mbc3TimerRamBattery | Mbc3RamBattery | Mbc5RamBattery |
+            Mbc5RumbleRamBattery | Mbc7SensorRumbleRamBattery | HuC1RamBattery => true,
+            _ => false,
+        }
+    }
+}
+
+/// The ROM size (0x148). Regular sizes are 32KB shifted left by the code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RomSize {
+    Kb32,
+    Kb64,
+    Kb128,
+    Kb256,
+    Kb512,
+    Mb1,
+    Mb2,
+    Mb4,
+    Mb8,
+    // only found in a few unofficial sources, in 16KB banks: 72, 80 and 96.
+    Mb1_1,
+    Mb1_2,
+    Mb1_5,
+}
+
+impl RomSize {
+    pub fn from_code(code: u8) -> Result<Self, CartridgeError> {
+        use self::RomSize::*;
+
+        let size = match code {
+            0x00 => Kb32,
+            0x01 => Kb64,
+            0x02 => Kb128,
+            0x03 => Kb256,
+            0x04 => Kb512,
+            0x05 => Mb1,
+            0x06 => Mb2,
+            0x07 => Mb4,
+            0x08 => Mb8,
+            0x52 => Mb1_1,
+            0x53 => Mb1_2,
+            0x54 => Mb1_5,
+            _ => return Err(CartridgeError::UnknownRomSize(code)),
+        };
+
+        Ok(size)
+    }
+
+    /// The number of 16KB ROM banks.
+    pub fn banks(&self) -> usize {
+        use self::RomSize::*;
+
+        match *self {
+            Kb32 => 2,
+            Kb64 => 4,
+            Kb128 => 8,
+            Kb256 => 16,
+            Kb512 => 32,
+            Mb1 => 64,
+            Mb2 => 128,
+            Mb4 => 256,
+            Mb8 => 512,
+            Mb1_1 => 72,
+            Mb1_2 => 80,
+            Mb1_5 => 96,
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.banks() * 0x4000
+    }
+}
+
+/// The amount of external RAM on the cartridge (0x149).
+/// MBC2 has RAM built in the controller, so its carts report None.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RamSize {
+    None,
+    // unused by official carts, but used by some homebrew.
+    Kb2,
+    Kb8,
+    Kb32,
+    Kb128,
+    Kb64,
+}
+
+impl RamSize {
+    pub fn from_code(code: u8) -> Result<Self, CartridgeError> {
+        use self::RamSize::*;
+
+        let size = match code {
+            0x00 => None,
+            0x01 => Kb2,
+            0x02 => Kb8,
+            0x03 => Kb32,
+            0x04 => Kb128,
+            0x05 => Kb64,
+            _ => return Err(CartridgeError::UnknownRamSize(code)),
+        };
+
+        Ok(size)
+    }
+
+    pub fn bytes(&self) -> usize {
+        use self::RamSize::*;
+
+        match *self {
+            None => 0,
+            Kb2 => 0x800,
+            Kb8 => 0x2000,
+            Kb32 => 0x8000,
+            Kb128 => 0x20000,
+            Kb64 => 0x10000,
+        }
+    }
+}
+
+/// Whether the game uses Color Gameboy features (0x143).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CgbSupport {
+    /// A DMG game, the flag byte is part of the title.
+    None,
+    /// Uses CGB features, but works on DMG too.
+    Compatible,
+    /// Only works on CGB.
+    Only,
+}
+
+/// The region the game is sold in (0x14A).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Destination {
+    Japanese,
+    Overseas,
+}
+
+/// The publisher of the game. Older games use a single byte code (0x14B), newer ones
+/// set it to 0x33 and use two ASCII characters at 0x144-0x145 instead.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Licensee {
+    Old(u8),
+    New(String),
+}
+
+impl Licensee {
+    /// Returns the name of the publisher, if the code is known.
+    pub fn name(&self) -> Option<&'static str> {
+        match *self {
+            Licensee::Old(code) => old_licensee_name(code),
+            Licensee::New(ref code) => new_licensee_name(code),
+        }
+    }
+}
+
+/// The metadata stored in the cartridge header, at 0x100-0x14F.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub cgb: CgbSupport,
+    pub licensee: Licensee,
+    pub sgb: bool,
+    pub cartridge_type: CartridgeType,
+    pub rom_size: RomSize,
+    pub ram_size: RamSize,
+    pub destination: Destination,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    /// Parses the header of the ROM in `data`.
+    pub fn parse(data: &[u8]) -> Result<Self, CartridgeError> {
+        if data.len() < HEADER_END {
+            return Err(CartridgeError::TooShort(data.len()));
+        }
+
+        let cgb = match data[0x143] {
+            0xC0 => CgbSupport::Only,
+            flag if flag & 0x80 != 0 => CgbSupport::Compatible,
+            _ => CgbSupport::None,
+        };
+
+        // the title used to be 16 characters long, the CGB flag took the last one.
+        let title_end = if cgb == CgbSupport::None { 0x144 } else { 0x143 };
+        let title: String = data[0x134..title_end].iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as char)
+            .collect();
+
+        let licensee = match data[0x14B] {
+            0x33 => Licensee::New(data[0x144..0x146].iter().map(|&c| c as char).collect()),
+            code => Licensee::Old(code),
+        };
+
+        let destination = match data[0x14A] {
+            0x00 => Destination::Japanese,
+            _ => Destination::Overseas,
+        };
+
+        Ok(CartridgeHeader {
+            title: title.trim_end().to_string(),
+            cgb: cgb,
+            licensee: licensee,
+            sgb: data[0x146] == 0x03,
+            cartridge_type: CartridgeType::from_code(data[0x147])?,
+            rom_size: RomSize::from_code(data[0x148])?,
+            ram_size: RamSize::from_code(data[0x149])?,
+            destination: destination,
+            version: data[0x14C],
+            header_checksum: data[0x14D],
+            global_checksum: combine_as_u16(data[0x14E], data[0x14F]),
+        })
+    }
+}
+
+/// Computes the header checksum over 0x134-0x14C, the same way the boot ROM does.
+pub fn header_checksum(data: &[u8]) -> u8 {
+    data[0x134..0x14D].iter()
+        .fold(0u8, |acc, &b| acc.wrapping_sub(b).wrapping_sub(1))
+}
+
+/// Computes the global checksum, the sum of all the bytes in the ROM except the checksum itself.
+pub fn global_checksum(data: &[u8]) -> u16 {
+    data.iter()
+        .enumerate()
+        .filter(|&(i, _)| i != 0x14E && i != 0x14F)
+        .fold(0u16, |acc, (_, &b)| acc.wrapping_add(b as u16))
+}
+
+#[cfg(test)]
+fn test_header() -> Vec<u8> {
+    let mut data = vec![0; 0x8000];
+    data[0x134..0x143].copy_from_slice(b"POKEMON YELLOW\0");
+    data[0x143] = 0x80;
+    data[0x144] = b'0';
+    data[0x145] = b'1';
+    data[0x146] = 0x03;
+    data[0x147] = 0x1B;
+    data[0x148] = 0x05;
+    data[0x149] = 0x03;
+    data[0x14A] = 0x01;
+    data[0x14B] = 0x33;
+    data[0x14C] = 0x02;
+    data
+}
+
+#[test]
+fn parse_header() {
+    let header = CartridgeHeader::parse(&test_header()).unwrap();
+
+    assert_eq!("POKEMON YELLOW", header.title);
+    assert_eq!(CgbSupport::Compatible, header.cgb);
+    assert_eq!(Licensee::New("01".to_string()), header.licensee);
+    assert_eq!(Some("Nintendo R&D1"), header.licensee.name());
+    assert!(header.sgb);
+    assert_eq!(CartridgeType::Mbc5RamBattery, header.cartridge_type);
+    assert!(header.cartridge_type.has_battery());
+    assert_eq!(64, header.rom_size.banks());
+    assert_eq!(0x8000, header.ram_size.bytes());
+    assert_eq!(Destination::Overseas, header.destination);
+    assert_eq!(2, header.version);
+}
+
+#[test]
+fn parse_dmg_header() {
+    let mut data = test_header();
+    // a 16 characters title, padded with spaces
+    data[0x134..0x144].copy_from_slice(b"TETRIS          ");
+    data[0x14B] = 0x01;
+
+    let header = CartridgeHeader::parse(&data).unwrap();
+    assert_eq!("TETRIS", header.title);
+    assert_eq!(CgbSupport::None, header.cgb);
+    assert_eq!(Some("Nintendo"), header.licensee.name());
+}
+
+#[test]
+fn parse_unknown_codes() {
+    let mut data = test_header();
+    data[0x147] = 0x04;
+    match CartridgeHeader::parse(&data) {
+        Err(CartridgeError::UnknownCartridgeType(0x04)) => {},
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    data = test_header();
+    data[0x148] = 0x09;
+    match CartridgeHeader::parse(&data) {
+        Err(CartridgeError::UnknownRomSize(0x09)) => {},
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    data = test_header();
+    data[0x149] = 0x06;
+    match CartridgeHeader::parse(&data) {
+        Err(CartridgeError::UnknownRamSize(0x06)) => {},
+        other => panic!("unexpected result {:?}", other),
+    }
+}
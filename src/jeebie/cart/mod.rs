@@ -0,0 +1,222 @@
+pub mod header;
+mod licensee;
+
+use std::fmt;
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+
+use self::header::{CartridgeHeader, HEADER_END, NINTENDO_LOGO};
+
+/// Errors that can occur when loading a cartridge.
+#[derive(Debug)]
+pub enum CartridgeError {
+    /// The ROM file could not be read.
+    Io(io::Error),
+    /// The data is too short to contain a header, the length is included.
+    TooShort(usize),
+    /// The data is shorter than the ROM size in the header (and at least 32KB), the length and
+    /// the expected size are included.
+    Truncated { len: usize, expected: usize },
+    UnknownCartridgeType(u8),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CartridgeError::Io(ref err) => write!(f, "Could not read the ROM file: {}", err),
+            CartridgeError::TooShort(len) => write!(f, "ROM is too short to contain a header ({} bytes, at least {} required)", len, HEADER_END),
+            CartridgeError::Truncated { len, expected } =>
+                write!(f, "ROM is truncated ({} bytes, {} expected from the header)", len, expected),
+            CartridgeError::UnknownCartridgeType(code) => write!(f, "Unknown cartridge type ${:02x}", code),
+            CartridgeError::UnknownRomSize(code) => write!(f, "Unknown ROM size ${:02x}", code),
+            CartridgeError::UnknownRamSize(code) => write!(f, "Unknown RAM size ${:02x}", code),
+        }
+    }
+}
+
+impl Error for CartridgeError {}
+
+impl From<io::Error> for CartridgeError {
+    fn from(err: io::Error) -> Self {
+        CartridgeError::Io(err)
+    }
+}
+
+/// The checks done by the DMG boot ROM on the cartridge header, in the order they're done.
+/// A cartridge failing any of them locks up on real hardware.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValidationError {
+    /// The logo at 0x104 differs, the offset of the first wrong byte is included.
+    Logo(usize),
+    /// The header checksum at 0x14D is wrong.
+    HeaderChecksum { expected: u8, found: u8 },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValidationError::Logo(offset) => write!(f, "Nintendo logo mismatch at ${:04x}", 0x104 + offset),
+            ValidationError::HeaderChecksum { expected, found } =>
+                write!(f, "Header checksum mismatch, expected ${:02x} but found ${:02x}", expected, found),
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+/// A struct representing data contained in a gameboy cartridge (a.k.a. ROM).
+/// Each Cartridge has an header with metadata (name, available hw on cart like rumble, ram, etc.)
+/// Data contained in a cartridge is exposed as a Vec<u8> but should be accessed via the MMU.
+#[derive(Debug)]
+pub struct Cartridge {
+    pub size: usize,
+    pub header: CartridgeHeader,
+    pub data: Vec<u8>,
+}
+
+impl Cartridge {
+    /// Creates a Cartridge by loading the file at the specified path.
+    pub fn new_with_path(path: &str) -> Result<Cartridge, CartridgeError> {
+        let data = Cartridge::load_rom_file(path)?;
+        Cartridge::new_with_vec(data)
+    }
+
+    /// Creates a new Cartridge struct from a vector buffer.
+    /// An error is returned if the header is missing or contains unknown values, or if the data
+    /// is shorter than the ROM size in the header.
+    pub fn new_with_vec(data: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+        let header = CartridgeHeader::parse(&data)?;
+
+        // the MBCs read the first 32KB unchecked, even when the header declares less
+        let expected = header.rom_size.bytes().max(0x8000);
+        if data.len() < expected {
+            return Err(CartridgeError::Truncated { len: data.len(), expected: expected });
+        }
+
+        Ok(Cartridge {
+            size: data.len(),
+            header: header,
+            data: data,
+        })
+    }
+
+    /// Returns true if the cartridge has a battery, meaning that the content
+    /// of the external RAM (and RTC) should be persisted.
+    pub fn has_battery(&self) -> bool {
+        self.header.cartridge_type.has_battery()
+    }
+
+    /// Returns true if the header checksum (0x14D) matches the header content.
+    /// This is verified by the boot ROM, which locks up on a mismatch.
+    pub fn verify_header_checksum(&self) -> bool {
+        header::header_checksum(&self.data) == self.header.header_checksum
+    }
+
+    /// Runs the same checks as the DMG boot ROM: the Nintendo logo first, then the header checksum.
+    /// Returns the first check that fails, if any.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let logo = &self.data[0x104..0x134];
+        if let Some(offset) = logo.iter().zip(NINTENDO_LOGO.iter()).position(|(a, b)| a != b) {
+            return Err(ValidationError::Logo(offset));
+        }
+
+        if !self.verify_header_checksum() {
+            return Err(ValidationError::HeaderChecksum {
+                expected: header::header_checksum(&self.data),
+                found: self.header.header_checksum,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the global checksum (0x14E-0x14F) matches the whole ROM.
+    /// Real hardware never verifies this.
+    pub fn verify_global_checksum(&self) -> bool {
+        header::global_checksum(&self.data) == self.header.global_checksum
+    }
+
+    /// Loads binary data from a file into a vector buffer.
+    fn load_rom_file(path: &str) -> Result<Vec<u8>, io::Error> {
+        let mut buf: Vec<u8> = vec![];
+        let mut file = File::open(path)?;
+        file.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+#[test]
+fn new_with_vec_rejects_short_data() {
+    match Cartridge::new_with_vec(vec![0; 0x100]) {
+        Err(CartridgeError::TooShort(0x100)) => {},
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn new_with_vec_rejects_truncated_data() {
+    let mut data = vec![0; 0x150];
+    data[0x134..0x13A].copy_from_slice(b"JEEBIE");
+    match Cartridge::new_with_vec(data.clone()) {
+        Err(CartridgeError::Truncated { len: 0x150, expected: 0x8000 }) => {},
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    // 64KB declared in the header
+    data.resize(0x8000, 0);
+    data[0x148] = 0x01;
+    match Cartridge::new_with_vec(data.clone()) {
+        Err(CartridgeError::Truncated { len: 0x8000, expected: 0x10000 }) => {},
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    data.resize(0x10000, 0);
+    assert!(Cartridge::new_with_vec(data).is_ok());
+}
+
+#[test]
+fn checksums() {
+    let mut data = vec![0; 0x8000];
+    data[0x134..0x13A].copy_from_slice(b"JEEBIE");
+    data[0x7FFF] = 0x10;
+
+    let mut cart = Cartridge::new_with_vec(data.clone()).unwrap();
+    assert!(!cart.verify_header_checksum());
+    assert!(!cart.verify_global_checksum());
+
+    data[0x14D] = header::header_checksum(&data);
+    let global = header::global_checksum(&data);
+    data[0x14E] = (global >> 8) as u8;
+    data[0x14F] = global as u8;
+
+    cart = Cartridge::new_with_vec(data).unwrap();
+    assert!(cart.verify_header_checksum());
+    assert!(cart.verify_global_checksum());
+}
+
+#[test]
+fn validate() {
+    let mut data = vec![0; 0x8000];
+    data[0x134..0x13A].copy_from_slice(b"JEEBIE");
+
+    let cart = Cartridge::new_with_vec(data.clone()).unwrap();
+    assert_eq!(Err(ValidationError::Logo(0)), cart.validate());
+
+    data[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+    data[0x120] ^= 0xFF;
+    let cart = Cartridge::new_with_vec(data.clone()).unwrap();
+    assert_eq!(Err(ValidationError::Logo(0x1C)), cart.validate());
+
+    data[0x120] ^= 0xFF;
+    let expected = header::header_checksum(&data);
+    let cart = Cartridge::new_with_vec(data.clone()).unwrap();
+    assert_eq!(Err(ValidationError::HeaderChecksum { expected: expected, found: 0 }), cart.validate());
+
+    data[0x14D] = expected;
+    let cart = Cartridge::new_with_vec(data).unwrap();
+    assert_eq!(Ok(()), cart.validate());
+}
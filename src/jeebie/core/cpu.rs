@@ -1,20 +1,43 @@
 use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 use jeebie::memory::MMU;
 use jeebie::core::registers::*;
 use jeebie::core::registers::Flags::*;
 use jeebie::cart::Cartridge;
+use jeebie::interrupt::Interrupt;
+use jeebie::joypad::Button;
+use jeebie::bootrom::{BootRom, Model};
 
 use jeebie::instr::opcodes::{ CB_OPCODE_TABLE, OPCODE_TABLE };
 use jeebie::utils::{ is_set, swap_bit, set_bit, reset_bit, combine_as_u16 };
 
+/// The execution state of the CPU.
+/// Besides running normally, the CPU can be put in a low power mode by the HALT and STOP
+/// instructions, or hang forever after fetching one of the illegal opcodes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Running,
+    Halted,
+    Stopped,
+    Locked,
+}
+
 #[derive(Debug)]
 pub struct CPU {
     pub reg: Registers,
     pub mem: Box<MMU>,
     pub interrupts_enabled: bool,
+    pub state: State,
+    // set when the HALT bug is triggered, the next fetch will not increment PC.
+    halt_bug: bool,
+    // set by EI, IME is enabled after the next instruction.
+    ime_scheduled: bool,
     // amount of machine cycles (as reported in timing tables) elapsed.
     cycles: u64,
+    // where the battery backed RAM is persisted, only set for carts with a battery.
+    save_path: Option<PathBuf>,
 }
 
 impl CPU {
@@ -26,29 +49,106 @@ impl CPU {
             reg: Registers::new(),
             mem: Box::new(MMU::new()),
             cycles: 0,
-            interrupts_enabled: true,
+            interrupts_enabled: false,
+            state: State::Running,
+            halt_bug: false,
+            ime_scheduled: false,
+            save_path: None,
         }
     }
 
     /// Creates a CPU with the provided MMU.
     pub fn with_mmu(mmu: MMU) -> CPU {
         let r = Registers::new();
-        CPU { reg: r, mem: Box::new(mmu), cycles: 0, interrupts_enabled: false}
+        CPU {
+            reg: r,
+            mem: Box::new(mmu),
+            cycles: 0,
+            interrupts_enabled: false,
+            state: State::Running,
+            halt_bug: false,
+            ime_scheduled: false,
+            save_path: None,
+        }
     }
 
     pub fn new_with_path(path: &str) -> Result<CPU, Box<dyn Error>>{
         let cart = Cartridge::new_with_path(path)?;
-        let mmu = MMU::new_with_rom(&cart);
+        let mut mmu = MMU::new_with_rom(&cart)?;
+
+        let mut save_path = None;
+        if cart.has_battery() {
+            let path = Path::new(path).with_extension("sav");
+            if path.exists() {
+                mmu.load_battery_data(&fs::read(&path)?)?;
+            }
+            save_path = Some(path);
+        }
+
+        let mut cpu = CPU::with_mmu(mmu);
+        cpu.save_path = save_path;
+        Ok(cpu)
+    }
 
-        Ok(CPU::with_mmu(mmu))
+    /// Replaces the boot ROM run at startup, see `MMU::set_boot_rom`.
+    pub fn set_boot_rom(&mut self, boot_rom: BootRom) {
+        self.mem.set_boot_rom(boot_rom);
+    }
+
+    /// Skips the boot ROM, starting the cartridge at 0x100 with the CPU and I/O registers
+    /// in the same state the boot ROM of `model` would leave them.
+    pub fn skip_boot(&mut self, model: Model) {
+        let header_checksum = self.mem.read_b(0x14D);
+        let cgb_game = is_set(self.mem.read_b(0x143), 7);
+
+        self.mem.skip_boot(model);
+        self.reg = Registers::post_boot(model, header_checksum, cgb_game);
+    }
+
+    /// Writes the battery backed RAM (and RTC) of the cartridge to its .sav file,
+    /// next to the ROM. Does nothing if the cartridge has no battery.
+    pub fn save_battery(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(ref path) = self.save_path {
+            fs::write(path, self.mem.battery_data())?;
+        }
+
+        Ok(())
     }
 
     /// Executes one instruction, updating cycles and PC register accordingly.
+    /// If an interrupt is serviced, it takes the place of the instruction.
     /// Returns the number of elapsed machine cycles.
     pub fn exec(&mut self) -> u32 {
+        // a halted CPU wakes up as soon as an interrupt is pending, even if IME is disabled.
+        if self.state == State::Halted && self.mem.interrupts.pending() != 0 {
+            self.state = State::Running;
+        }
+
+        // while not running no instruction is fetched, but time still passes for the rest
+        // of the system.
+        if self.state != State::Running {
+            self.cycles = self.cycles.wrapping_add(4);
+            return 4;
+        }
+
+        let interrupt_timing = self.check_interrupts();
+        if interrupt_timing > 0 {
+            self.cycles = self.cycles.wrapping_add(interrupt_timing as u64);
+            return interrupt_timing;
+        }
+
+        // EI only takes effect after the instruction following it
+        let ime_scheduled = self.ime_scheduled;
+
         // fetch
         let opcode = self.mem.read_b(self.reg.pc);
-        self.reg.pc = self.reg.pc.wrapping_add(1);
+
+        // with the HALT bug PC fails to increment, so the byte after HALT is read twice.
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.reg.pc = self.reg.pc.wrapping_add(1);
+        }
 
         let instr_timing = match opcode {
             0xCB => {
@@ -63,6 +163,12 @@ impl CPU {
             }
         };
 
+        // if a DI was executed in the meantime, the EI is cancelled.
+        if ime_scheduled && self.ime_scheduled {
+            self.ime_scheduled = false;
+            self.interrupts_enabled = true;
+        }
+
         self.cycles = self.cycles.wrapping_add(instr_timing as u64);
         instr_timing as u32
     }
@@ -83,34 +189,52 @@ impl CPU {
         self.mem.gpu.get_framebuffer()
     }
 
-    pub fn check_interrupts(&mut self) {
+    /// Presses a button on the joypad.
+    /// A button press also brings the CPU out of STOP mode.
+    pub fn press(&mut self, button: Button) {
+        let mem = &mut *self.mem;
+        mem.joypad.press(button, &mut mem.interrupts);
+
+        if self.state == State::Stopped {
+            self.state = State::Running;
+        }
+    }
+
+    /// Releases a button on the joypad.
+    pub fn release(&mut self, button: Button) {
+        self.mem.joypad.release(button);
+    }
+
+    /// Services the highest priority pending interrupt, if IME is set.
+    /// The interrupt is acknowledged, IME is disabled and the CPU jumps to the interrupt vector.
+    /// Returns the cycles taken to dispatch the interrupt, 0 if nothing was serviced.
+    pub fn check_interrupts(&mut self) -> u32 {
         if !self.interrupts_enabled {
-            return;
+            return 0;
         }
 
-        for i in 0..5 {
-            let mut int_flag = self.mem.read_b(0xFF0F);
-        
-            if is_set(int_flag, i) {
-                int_flag &= 0xFF - (1 << i);
-                self.mem.write_b(0xFF0F, int_flag);
+        match Interrupt::from_flags(self.mem.interrupts.pending()) {
+            Some(interrupt) => {
+                self.mem.interrupts.acknowledge(interrupt);
                 self.interrupts_enabled = false;
-
-                match i {
-                    0 => self.restart(0x40), // VBLANK
-                    1 => self.restart(0x48), // LCDC
-                    2 => self.restart(0x50), // TIMER
-                    3 => self.restart(0x58), // SERIAL
-                    4 => self.restart(0x60), // HILO
-                    _ => {}
-                }
-
-                self.cycles += 20;
-                break;
-            }
+                self.restart(interrupt.vector());
+                20
+            },
+            None => 0,
         }
     }
 
+    /// Enables interrupts (EI), IME is set only after the following instruction is executed.
+    pub fn enable_interrupts(&mut self) {
+        self.ime_scheduled = true;
+    }
+
+    /// Disables interrupts (DI), this takes effect immediately and cancels a pending EI.
+    pub fn disable_interrupts(&mut self) {
+        self.ime_scheduled = false;
+        self.interrupts_enabled = false;
+    }
+
     pub fn get8(&mut self, reg: Register8) -> u8 {
         match reg {
             Register8::A => self.reg.a,
@@ -163,7 +287,8 @@ impl CPU {
 
     pub fn set16(&mut self, reg: Register16, value: u16) {
         match reg {
-            Register16::AF => { self.reg.a = (value >> 8) as u8 ; self.reg.f = value as u8; },
+            // the 4 low bits of F are not used and always read back as 0
+            Register16::AF => { self.reg.a = (value >> 8) as u8 ; self.reg.f = (value as u8) & 0xF0; },
             Register16::BC => { self.reg.b = (value >> 8) as u8 ; self.reg.c = value as u8; },
             Register16::DE => { self.reg.d = (value >> 8) as u8 ; self.reg.e = value as u8; },
             Register16::HL => { self.reg.h = (value >> 8) as u8 ; self.reg.l = value as u8; },
@@ -229,16 +354,17 @@ impl CPU {
         self.reg.pc = addr;
     }
 
+    // The address is always read, so that immediates are consumed even if the jump is not taken.
     pub fn jump_flag(&mut self, flag: Flags, reg: Register16) {
+        let addr = self.get16(reg);
         if self.reg.is_set(flag) {
-            let addr = self.get16(reg);
             self.jump(addr);
         }
     }
 
     pub fn jump_not_flag(&mut self, flag: Flags, reg: Register16) {
+        let addr = self.get16(reg);
         if !self.reg.is_set(flag) {
-            let addr = self.get16(reg);
             self.jump(addr);
         }
     }
@@ -264,6 +390,30 @@ impl CPU {
         self.jump(addr);
     }
 
+    /// Puts the CPU in HALT mode, no instructions are executed until an interrupt is pending.
+    /// If IME is disabled and an interrupt is already pending, the CPU does not halt and
+    /// triggers the HALT bug instead (on DMG): the following byte is read twice.
+    pub fn halt(&mut self) {
+        if !self.interrupts_enabled && self.mem.interrupts.pending() != 0 {
+            self.halt_bug = true;
+        } else {
+            self.state = State::Halted;
+        }
+    }
+
+    /// Puts the CPU in STOP mode, the lowest power mode available.
+    /// STOP is a 2-byte instruction, the byte following the opcode is skipped.
+    pub fn stop(&mut self) {
+        self.reg.pc = self.reg.pc.wrapping_add(1);
+        self.state = State::Stopped;
+    }
+
+    /// Locks up the CPU, as it happens on hardware when an illegal opcode is executed.
+    /// The only way out of this state is a reset.
+    pub fn lock(&mut self) {
+        self.state = State::Locked;
+    }
+
     // Rotate the register left, old bit 7 goes to carry flag (RLC).
     pub fn rotate_left_carry(&mut self, reg: Register8) {
         self.reg.clear_all_flags();
@@ -331,6 +481,21 @@ impl CPU {
         self.set16(reg1, result);
     }
 
+    /// Computes the result of adding a signed 8-bit value to SP, storing it in `dest`.
+    /// This is used by both ADD SP,n and LD HL,SP+n. Zero and Sub are always cleared,
+    /// HalfCarry and Carry are computed on the low byte, as an unsigned 8-bit add.
+    pub fn compute_add_sp(&mut self, dest: Register16, reg: Register8) {
+        let sp = self.reg.sp;
+        let offset = self.get8(reg);
+        let result = sp.wrapping_add((offset as i8) as u16);
+
+        self.reg.clear_all_flags();
+        self.reg.set_or_clear(HalfCarry, (sp & 0x0F) + (offset as u16 & 0x0F) > 0x0F);
+        self.reg.set_or_clear(Carry, (sp & 0xFF) + (offset as u16) > 0xFF);
+
+        self.set16(dest, result);
+    }
+
     // Computes the flags and result for an 8-bit ADD instruction.
     pub fn compute_add(&mut self, reg1: Register8, reg2: Register8) {
         let lhs = self.get8(reg1);
@@ -348,10 +513,20 @@ impl CPU {
         self.set8(Register8::A, result);
     }
 
+    // Computes the flags and result for an 8-bit ADC instruction.
+    // The carry is added to the result and taken into account when computing flags.
     pub fn compute_adc(&mut self, reg1: Register8, reg2: Register8) {
         let carry = if self.reg.is_set(Carry) { 1 } else { 0 };
-        let reg2 = Register8::Value8(self.get8(reg2).wrapping_add(carry));
-        self.compute_add(reg1, reg2);
+        let lhs = self.get8(reg1);
+        let rhs = self.get8(reg2);
+        let result = lhs.wrapping_add(rhs).wrapping_add(carry);
+
+        self.reg.clear_all_flags();
+        self.reg.set_or_clear(Zero, result == 0);
+        self.reg.set_or_clear(Carry, (lhs as u16) + (rhs as u16) + (carry as u16) > 0xFF);
+        self.reg.set_or_clear(HalfCarry, (lhs & 0x0F) + (rhs & 0x0F) + carry > 0x0F);
+
+        self.set8(Register8::A, result);
     }
 
     // Computes the flags and result for a SUB instruction.
@@ -372,10 +547,21 @@ impl CPU {
         self.set8(Register8::A, result);
     }
 
+    // Computes the flags and result for a SBC instruction.
+    // Left hand operator is always register A, the carry is subtracted along with the operand.
     pub fn compute_sbc(&mut self, reg: Register8) {
         let carry = if self.reg.is_set(Carry) { 1 } else { 0 };
-        let reg = Register8::Value8(self.get8(reg).wrapping_sub(carry));
-        self.compute_sub(reg);
+        let lhs = self.get8(Register8::A);
+        let rhs = self.get8(reg);
+        let result = lhs.wrapping_sub(rhs).wrapping_sub(carry);
+
+        self.reg.clear_all_flags();
+        self.reg.set_flag(Sub);
+        self.reg.set_or_clear(Zero, result == 0);
+        self.reg.set_or_clear(Carry, (lhs as u16) < (rhs as u16) + (carry as u16));
+        self.reg.set_or_clear(HalfCarry, (lhs & 0x0F) < (rhs & 0x0F) + carry);
+
+        self.set8(Register8::A, result);
     }
 
     // Computes the flags for a CP instruction.
@@ -395,6 +581,61 @@ impl CPU {
         self.reg.set_or_clear(HalfCarry, (lhs & 0xF) < (rhs & 0xF));
     }
 
+    /// Computes a DAA instruction, adjusting register A to a valid BCD value after an
+    /// addition or subtraction. The Sub flag tells which operation was last performed.
+    pub fn compute_daa(&mut self) {
+        let mut value = self.reg.a;
+        let mut carry = self.reg.is_set(Carry);
+
+        if self.reg.is_set(Sub) {
+            if carry {
+                value = value.wrapping_sub(0x60);
+            }
+            if self.reg.is_set(HalfCarry) {
+                value = value.wrapping_sub(0x06);
+            }
+        } else {
+            if carry || value > 0x99 {
+                value = value.wrapping_add(0x60);
+                carry = true;
+            }
+            if self.reg.is_set(HalfCarry) || (value & 0x0F) > 0x09 {
+                value = value.wrapping_add(0x06);
+            }
+        }
+
+        // Sub is preserved, HalfCarry always cleared.
+        self.reg.clear_flag(HalfCarry);
+        self.reg.set_or_clear(Zero, value == 0);
+        self.reg.set_or_clear(Carry, carry);
+        self.reg.a = value;
+    }
+
+    /// Computes a CPL instruction, complementing register A.
+    /// Sub and HalfCarry are set, other flags are not affected.
+    pub fn compute_cpl(&mut self) {
+        self.reg.a = !self.reg.a;
+        self.reg.set_flag(Sub);
+        self.reg.set_flag(HalfCarry);
+    }
+
+    /// Computes a SCF instruction, setting the carry flag.
+    /// Zero is not affected, Sub and HalfCarry are cleared.
+    pub fn compute_scf(&mut self) {
+        self.reg.clear_flag(Sub);
+        self.reg.clear_flag(HalfCarry);
+        self.reg.set_flag(Carry);
+    }
+
+    /// Computes a CCF instruction, complementing the carry flag.
+    /// Zero is not affected, Sub and HalfCarry are cleared.
+    pub fn compute_ccf(&mut self) {
+        let carry = self.reg.is_set(Carry);
+        self.reg.clear_flag(Sub);
+        self.reg.clear_flag(HalfCarry);
+        self.reg.set_or_clear(Carry, !carry);
+    }
+
     // Computes the flags and result for an AND instruction.
     // lhs is always the register A
     pub fn compute_and(&mut self, reg: Register8) {
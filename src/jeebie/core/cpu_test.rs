@@ -1,4 +1,9 @@
-use jeebie::core::cpu::CPU;
+use jeebie::core::cpu::{CPU, State};
+use jeebie::memory::MMU;
+use jeebie::cart::Cartridge;
+use jeebie::bootrom::Model;
+use jeebie::interrupt::Interrupt;
+use jeebie::joypad::Button;
 use jeebie::core::registers::Register8::*;
 use jeebie::core::registers::Register16::*;
 use jeebie::core::registers::Flags::*;
@@ -442,3 +447,332 @@ fn shift_r_test() {
     assert_eq!(0, cpu.get8(A));
     assert_eq!(Zero as u8, cpu.reg.f);
 }
+
+#[test]
+fn adc_sbc_test() {
+    let mut cpu = CPU::new();
+
+    // 0x0F + 0x00 + carry, carry goes into the low nibble
+    cpu.set8(A, 0x0F);
+    cpu.set8(B, 0x00);
+    cpu.reg.set_flag(Carry);
+    cpu.compute_adc(A, B);
+    assert_eq!(0x10, cpu.get8(A));
+    assert_eq!(HalfCarry as u8, cpu.reg.f);
+
+    // 0x00 + 0xFF + carry overflows to zero
+    cpu.set8(A, 0x00);
+    cpu.set8(B, 0xFF);
+    cpu.reg.set_flag(Carry);
+    cpu.compute_adc(A, B);
+    assert_eq!(0x00, cpu.get8(A));
+    assert_eq!(Zero as u8 | HalfCarry as u8 | Carry as u8, cpu.reg.f);
+
+    // 0x00 - 0xFF - carry borrows
+    cpu.set8(A, 0x00);
+    cpu.set8(B, 0xFF);
+    cpu.reg.set_flag(Carry);
+    cpu.compute_sbc(B);
+    assert_eq!(0x00, cpu.get8(A));
+    assert_eq!(Zero as u8 | Sub as u8 | HalfCarry as u8 | Carry as u8, cpu.reg.f);
+}
+
+#[test]
+fn add_sp_test() {
+    let mut cpu = CPU::new();
+
+    // SP + (-1), carries out of both nibble and byte
+    cpu.set16(SP, 0x00FF);
+    cpu.compute_add_sp(HL, Value8(0xFF));
+    assert_eq!(0x00FE, cpu.get16(HL));
+    assert_eq!(0x00FF, cpu.get16(SP));
+    assert_eq!(HalfCarry as u8 | Carry as u8, cpu.reg.f);
+
+    // zero flag is never set
+    cpu.set16(SP, 0xFFF8);
+    cpu.compute_add_sp(SP, Value8(0x08));
+    assert_eq!(0x0000, cpu.get16(SP));
+    assert_eq!(HalfCarry as u8 | Carry as u8, cpu.reg.f);
+}
+
+#[test]
+fn daa_test() {
+    let mut cpu = CPU::new();
+
+    // 0x15 + 0x27 = 0x3C -> 0x42 in BCD
+    cpu.set8(A, 0x15);
+    cpu.set8(B, 0x27);
+    cpu.compute_add(A, B);
+    cpu.compute_daa();
+    assert_eq!(0x42, cpu.get8(A));
+    assert_eq!(0, cpu.reg.f);
+
+    // 0x99 + 0x01 = 0x9A -> 0x00 in BCD, with carry
+    cpu.set8(A, 0x99);
+    cpu.set8(B, 0x01);
+    cpu.compute_add(A, B);
+    cpu.compute_daa();
+    assert_eq!(0x00, cpu.get8(A));
+    assert_eq!(Zero as u8 | Carry as u8, cpu.reg.f);
+
+    // 0x42 - 0x15 = 0x2D -> 0x27 in BCD
+    cpu.set8(A, 0x42);
+    cpu.set8(B, 0x15);
+    cpu.compute_sub(B);
+    cpu.compute_daa();
+    assert_eq!(0x27, cpu.get8(A));
+    assert_eq!(Sub as u8, cpu.reg.f);
+}
+
+#[test]
+fn carry_flag_ops_test() {
+    let mut cpu = CPU::new();
+
+    cpu.set8(A, 0b1010_0101);
+    cpu.reg.set_flag(Zero);
+    cpu.compute_cpl();
+    assert_eq!(0b0101_1010, cpu.get8(A));
+    assert_eq!(Zero as u8 | Sub as u8 | HalfCarry as u8, cpu.reg.f);
+
+    // zero is preserved
+    cpu.compute_scf();
+    assert_eq!(Zero as u8 | Carry as u8, cpu.reg.f);
+
+    cpu.compute_ccf();
+    assert_eq!(Zero as u8, cpu.reg.f);
+
+    cpu.compute_ccf();
+    assert_eq!(Zero as u8 | Carry as u8, cpu.reg.f);
+}
+
+#[test]
+fn pop_af_masks_flags_test() {
+    let mut cpu = CPU::new();
+
+    cpu.set16(SP, 0xFFFE);
+    cpu.push_stack(Value16(0x12FF));
+    cpu.pop_stack(AF);
+    assert_eq!(0x12, cpu.get8(A));
+    assert_eq!(0xF0, cpu.reg.f);
+}
+
+#[test]
+fn illegal_opcode_locks_test() {
+    let mut cpu = CPU::new();
+
+    cpu.set16(PC, 0xC000);
+    cpu.set8(Address(0xC000), 0xD3);
+    cpu.exec();
+    assert_eq!(State::Locked, cpu.state);
+    assert_eq!(0xC001, cpu.get16(PC));
+
+    // no more instructions are fetched
+    cpu.exec();
+    assert_eq!(0xC001, cpu.get16(PC));
+}
+
+#[test]
+fn halt_wakes_up_on_pending_interrupt_test() {
+    let mut cpu = CPU::new();
+    cpu.interrupts_enabled = false;
+
+    // HALT; INC A
+    cpu.set16(PC, 0xC000);
+    cpu.set8(Address(0xC000), 0x76);
+    cpu.set8(Address(0xC001), 0x3C);
+    cpu.set8(Address(0xFFFF), 0x01);
+
+    cpu.exec();
+    assert_eq!(State::Halted, cpu.state);
+
+    // no interrupt requested, cpu stays halted
+    assert_eq!(4, cpu.exec());
+    assert_eq!(State::Halted, cpu.state);
+    assert_eq!(0xC001, cpu.get16(PC));
+
+    // VBlank requested, cpu wakes up even with IME disabled
+    cpu.set8(Address(0xFF0F), 0x01);
+    cpu.exec();
+    assert_eq!(State::Running, cpu.state);
+    assert_eq!(0xC002, cpu.get16(PC));
+    assert_eq!(1, cpu.get8(A));
+}
+
+#[test]
+fn halt_bug_test() {
+    let mut cpu = CPU::new();
+    cpu.interrupts_enabled = false;
+
+    // HALT; INC A
+    cpu.set16(PC, 0xC000);
+    cpu.set8(Address(0xC000), 0x76);
+    cpu.set8(Address(0xC001), 0x3C);
+    cpu.set8(Address(0xC002), 0x00);
+
+    // interrupt already pending with IME disabled triggers the bug
+    cpu.set8(Address(0xFFFF), 0x01);
+    cpu.set8(Address(0xFF0F), 0x01);
+
+    cpu.exec();
+    assert_eq!(State::Running, cpu.state);
+
+    // INC A is executed twice
+    cpu.exec();
+    assert_eq!(0xC001, cpu.get16(PC));
+    cpu.exec();
+    assert_eq!(0xC002, cpu.get16(PC));
+    assert_eq!(2, cpu.get8(A));
+}
+
+#[test]
+fn interrupt_dispatch_test() {
+    let mut cpu = CPU::new();
+    cpu.interrupts_enabled = true;
+    cpu.set16(SP, 0xFFFE);
+    cpu.set16(PC, 0xC000);
+
+    // Timer and Joypad requested, only Joypad enabled
+    cpu.set8(Address(0xFFFF), 0x10);
+    cpu.mem.interrupts.request(Interrupt::Timer);
+    cpu.mem.interrupts.request(Interrupt::Joypad);
+
+    assert_eq!(20, cpu.exec());
+    assert_eq!(0x60, cpu.get16(PC));
+    assert!(!cpu.interrupts_enabled);
+    // only the serviced interrupt is acknowledged
+    assert_eq!(0xE4, cpu.get8(Address(0xFF0F)));
+
+    // RETI returns and re-enables IME
+    cpu.set16(PC, 0xC100);
+    cpu.set8(Address(0xC100), 0xD9);
+    cpu.mem.interrupts.write_enable(0);
+    cpu.exec();
+    assert_eq!(0xC000, cpu.get16(PC));
+    assert!(cpu.interrupts_enabled);
+}
+
+#[test]
+fn ei_delay_test() {
+    let mut cpu = CPU::new();
+    cpu.set16(SP, 0xFFFE);
+    cpu.set16(PC, 0xC000);
+
+    // EI; NOP; NOP
+    cpu.set8(Address(0xC000), 0xFB);
+    cpu.set8(Address(0xC001), 0x00);
+    cpu.set8(Address(0xC002), 0x00);
+    cpu.set8(Address(0xFFFF), 0x01);
+    cpu.mem.interrupts.request(Interrupt::VBlank);
+
+    cpu.exec();
+    assert!(!cpu.interrupts_enabled);
+    // the instruction after EI is executed before IME is set
+    cpu.exec();
+    assert_eq!(0xC002, cpu.get16(PC));
+    assert!(cpu.interrupts_enabled);
+
+    cpu.exec();
+    assert_eq!(0x40, cpu.get16(PC));
+}
+
+#[test]
+fn di_cancels_ei_test() {
+    let mut cpu = CPU::new();
+    cpu.set16(PC, 0xC000);
+
+    // EI; DI
+    cpu.set8(Address(0xC000), 0xFB);
+    cpu.set8(Address(0xC001), 0xF3);
+
+    cpu.exec();
+    cpu.exec();
+    assert!(!cpu.interrupts_enabled);
+    cpu.exec();
+    assert!(!cpu.interrupts_enabled);
+}
+
+#[test]
+fn halt_services_interrupt_on_wake_up_test() {
+    let mut cpu = CPU::new();
+    cpu.interrupts_enabled = true;
+    cpu.set16(SP, 0xFFFE);
+    cpu.set16(PC, 0xC000);
+
+    // HALT
+    cpu.set8(Address(0xC000), 0x76);
+    cpu.set8(Address(0xFFFF), 0x04);
+    cpu.exec();
+    assert_eq!(State::Halted, cpu.state);
+
+    cpu.mem.interrupts.request(Interrupt::Timer);
+    assert_eq!(20, cpu.exec());
+    assert_eq!(State::Running, cpu.state);
+    assert_eq!(0x50, cpu.get16(PC));
+
+    // return address is the instruction after HALT
+    cpu.pop_stack(HL);
+    assert_eq!(0xC001, cpu.get16(HL));
+}
+
+#[test]
+fn press_wakes_up_from_stop_test() {
+    let mut cpu = CPU::new();
+    cpu.set16(PC, 0xC000);
+
+    // STOP 0
+    cpu.set8(Address(0xC000), 0x10);
+    cpu.set8(Address(0xC001), 0x00);
+    cpu.exec();
+    assert_eq!(State::Stopped, cpu.state);
+    assert_eq!(0xC002, cpu.get16(PC));
+
+    cpu.press(Button::Start);
+    assert_eq!(State::Running, cpu.state);
+    cpu.release(Button::Start);
+}
+
+#[test]
+fn skip_boot() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x14D] = 0xE7;
+    rom[0x00] = 0x42;
+    let cart = Cartridge::new_with_vec(rom).unwrap();
+    let mut cpu = CPU::with_mmu(MMU::new_with_rom(&cart).unwrap());
+
+    cpu.skip_boot(Model::DMG);
+    assert_eq!(0x01B0, cpu.get16(AF));
+    assert_eq!(0x0013, cpu.get16(BC));
+    assert_eq!(0x00D8, cpu.get16(DE));
+    assert_eq!(0x014D, cpu.get16(HL));
+    assert_eq!(0xFFFE, cpu.get16(SP));
+    assert_eq!(0x0100, cpu.get16(PC));
+
+    // the boot ROM is unmapped and the I/O registers are set
+    assert_eq!(0x42, cpu.mem.read_b(0x0000));
+    assert_eq!(0x91, cpu.mem.read_b(0xFF40));
+    assert_eq!(0xAB, cpu.mem.read_b(0xFF04));
+    assert_eq!(0xCF, cpu.mem.read_b(0xFF00));
+    assert_eq!(0xE1, cpu.mem.read_b(0xFF0F));
+    assert_eq!(0x77, cpu.mem.read_b(0xFF24));
+    assert_eq!(0xF1, cpu.mem.read_b(0xFF26));
+    assert_eq!(0xF3, cpu.mem.read_b(0xFF12));
+    assert_eq!(0x00, cpu.mem.read_b(0xFF44));
+    assert_eq!(0x85, cpu.mem.read_b(0xFF41));
+    assert_eq!(0x7E, cpu.mem.read_b(0xFF02));
+    assert_eq!(0xFF, cpu.mem.read_b(0xFF46));
+
+    cpu.skip_boot(Model::DMG0);
+    assert_eq!(0x18, cpu.mem.read_b(0xFF04));
+    assert_eq!(0xF1, cpu.mem.read_b(0xFF26));
+    assert_eq!(0x91, cpu.mem.read_b(0xFF44));
+    assert_eq!(0x81, cpu.mem.read_b(0xFF41));
+
+    cpu.skip_boot(Model::CGB);
+    assert_eq!(0x1180, cpu.get16(AF));
+    assert_eq!(0x0008, cpu.get16(DE));
+    assert_eq!(0x1E, cpu.mem.read_b(0xFF04));
+    assert_eq!(0xF1, cpu.mem.read_b(0xFF26));
+    assert_eq!(0x85, cpu.mem.read_b(0xFF41));
+    assert_eq!(0x7F, cpu.mem.read_b(0xFF02));
+    assert_eq!(0x00, cpu.mem.read_b(0xFF46));
+}
@@ -1,3 +1,5 @@
+use jeebie::bootrom::Model;
+
 /// The four flags and their respective bit values. Bits 0-3 are unused.
 pub enum Flags {
     /// This bit is set when the result of a math operation
@@ -11,12 +13,12 @@ pub enum Flags {
 
     /// This bit is set if a carry occurred from the lower
     /// nibble in the last math operation.
-    Carry = 0b00100000,
+    HalfCarry = 0b00100000,
 
     /// This bit is set if a carry occurred from the last
     /// math operation or if register A is the smaller value
     /// when executing the CP instruction.
-    HalfCarry = 0b00010000,
+    Carry = 0b00010000,
 }
 
 #[derive(Clone, Copy)]
@@ -59,6 +61,33 @@ impl Registers {
         }
     }
 
+    /// Returns the registers as left by the boot ROM of `model`, right before jumping to 0x100.
+    /// The DMG boot ROM leaves H and C set unless the header checksum is 0, while the color
+    /// models load different values depending on `cgb_game`, the CGB flag of the cartridge.
+    pub fn post_boot(model: Model, header_checksum: u8, cgb_game: bool) -> Registers {
+        let mut reg = Registers::new();
+        reg.pc = 0x100;
+        reg.sp = 0xFFFE;
+
+        let dmg_flags = if header_checksum == 0 { 0x80 } else { 0xB0 };
+
+        let (a, f, b, c, d, e, h, l) = match model {
+            Model::DMG0 => (0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03),
+            Model::DMG => (0x01, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::MGB => (0xFF, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::CGB if cgb_game => (0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+            Model::CGB => (0x11, 0x80, 0x00, 0x00, 0x00, 0x08, 0x00, 0x7C),
+            Model::AGB if cgb_game => (0x11, 0x00, 0x01, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+            Model::AGB => (0x11, 0x00, 0x01, 0x00, 0x00, 0x08, 0x00, 0x7C),
+        };
+
+        reg.a = a; reg.f = f;
+        reg.b = b; reg.c = c;
+        reg.d = d; reg.e = e;
+        reg.h = h; reg.l = l;
+        reg
+    }
+
     /// Clears all flag values by resetting the F register to 0.
     pub fn clear_all_flags(&mut self) {
        self.f = 0;
@@ -8,20 +8,20 @@ pub static DISASM_TABLE : [&'static str; 256] = [
     "LD (nn),SP", "ADD HL,BC", "LD A,(BC)", "DEC BC",
     "INC C", "DEC C", "LD C,n", "RRCA",
     // 0x10
-    "Missing", "LD DE,nn", "LD (DE),A", "INC DE",
+    "STOP", "LD DE,nn", "LD (DE),A", "INC DE",
     "INC D", "DEC D", "LD D,n", "RLA",
     "JR n", "ADD HL,DE", "LD A,(DE)", "DEC DE",
     "INC E", "DEC E", "LD E,n", "RRA",
     // 0x20
     "JR NZ,*", "LD HL,nn", "LDI (HL),A", "INC HL",
-    "INC H", "DEC H", "LD H,n", "Missing",
+    "INC H", "DEC H", "LD H,n", "DAA",
     "JR Z,*", "ADD HL,HL", "LDI A,(HL)", "DEC HL",
-    "INC L", "DEC L", "LD L,n", "Missing",
+    "INC L", "DEC L", "LD L,n", "CPL",
     // 0x30
     "JR NC,*", "LD SP,nn", "LDD (HL),A", "INC SP",
-    "INC (HL)", "DEC (HL)", "LD (HL),n", "Missing",
+    "INC (HL)", "DEC (HL)", "LD (HL),n", "SCF",
     "JR C,*", "ADD HL,SP", "LDD A,(HL)", "DEC SP",
-    "INC A", "DEC A", "LD A,n", "Missing",
+    "INC A", "DEC A", "LD A,n", "CCF",
     // 0x40
     "LD B,B", "LD B,C", "LD B,D", "LD B,E",
     "LD B,H", "LD B,L", "LD B,(HL)", "LD B,A",
@@ -39,7 +39,7 @@ pub static DISASM_TABLE : [&'static str; 256] = [
     "LD L,H", "LD L,L", "LD L,(HL)", "LD L,A",
     // 0x70
     "LD (HL),B", "LD (HL),C", "LD (HL),D", "LD (HL),E",
-    "LD (HL),H", "LD (HL),L", "Missing", "LD (HL),A",
+    "LD (HL),H", "LD (HL),L", "HALT", "LD (HL),A",
     "LD A,B", "LD A,C", "LD A,D", "LD A,E",
     "LD A,H", "LD A,L", "LD A,(HL)", "LD A,A",
     // 0x80
@@ -68,20 +68,20 @@ pub static DISASM_TABLE : [&'static str; 256] = [
     "RET Z", "RET", "JP Z,nn", "Missing",
     "CALL Z,nn", "CALL nn", "ADC A,*", "RST 08H",
     // 0xd0
-    "RET NC", "POP DE", "JP NC,nn", "Missing",
+    "RET NC", "POP DE", "JP NC,nn", "ILLEGAL",
     "CALL NC,nn", "PUSH DE", "SUB *", "RST 10H",
-    "RET C", "RETI", "JP C,nn", "Missing",
-    "CALL C,nn", "Missing", "SBC A,*", "RST 18H",
+    "RET C", "RETI", "JP C,nn", "ILLEGAL",
+    "CALL C,nn", "ILLEGAL", "SBC A,*", "RST 18H",
     // 0xe0
-    "LDH ($FF00+n),A", "POP HL", "LD ($FF00+C),A", "Missing",
-    "Missing", "PUSH HL", "AND *", "RST 20H",
-    "ADD SP,*", "JP (HL)", "LD (nn),A", "Missing",
-    "Missing", "Missing", "XOR *", "RST 28H",
+    "LDH ($FF00+n),A", "POP HL", "LD ($FF00+C),A", "ILLEGAL",
+    "ILLEGAL", "PUSH HL", "AND *", "RST 20H",
+    "ADD SP,*", "JP (HL)", "LD (nn),A", "ILLEGAL",
+    "ILLEGAL", "ILLEGAL", "XOR *", "RST 28H",
     // 0xf0
     "LDH A,($FF00+n)", "POP AF", "LD A,($FF00 + C)", "DI",
-    "Missing", "PUSH AF", "OR *", "RST 30H",
+    "ILLEGAL", "PUSH AF", "OR *", "RST 30H",
     "LD HL,SP+n", "LD SP,HL", "LD A,(nn)", "EI",
-    "Missing", "Missing", "CP *", "RST 38H",
+    "ILLEGAL", "ILLEGAL", "CP *", "RST 38H",
 ];
 
 pub static CB_DISASM_TABLE : [&'static str; 256] = [
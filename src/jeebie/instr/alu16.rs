@@ -1,6 +1,7 @@
 /// Module for 16 bit arithmetic (ALU instructions)
 
 use jeebie::core::cpu::CPU;
+use jeebie::core::registers::Register8::N;
 use jeebie::core::registers::Register16::*;
 
 // 'ADD HL,BC' 09 8
@@ -29,8 +30,7 @@ pub fn ADD_hl_sp(cpu: &mut CPU) -> i32 {
 
 // 'ADD SP,*' E8 16
 pub fn ADD_sp_n(cpu: &mut CPU) -> i32 {
-    let value = Value16(cpu.get_immediate8() as u16);
-    cpu.compute_add16(SP, value);
+    cpu.compute_add_sp(SP, N);
     16
 }
 
@@ -31,7 +31,7 @@ pub fn JP_NC_nn(cpu: &mut CPU) -> i32 {
 
 // 'JP C,nn' DA 12
 pub fn JP_C_nn(cpu: &mut CPU) -> i32 {
-    cpu.jump_flag(Flags::Zero, Register16::NN);
+    cpu.jump_flag(Flags::Carry, Register16::NN);
     12
 }
 
@@ -104,6 +104,9 @@ pub fn CALL_nn(cpu: &mut CPU) -> i32 {
 pub fn CALL_NZ_nn(cpu: &mut CPU) -> i32 {
     if !cpu.reg.is_set(Flags::Zero) {
         CALL_nn(cpu);
+    } else {
+        // skip the address immediate
+        cpu.reg.pc = cpu.reg.pc.wrapping_add(2);
     }
     12
 }
@@ -112,6 +115,9 @@ pub fn CALL_NZ_nn(cpu: &mut CPU) -> i32 {
 pub fn CALL_Z_nn(cpu: &mut CPU) -> i32 {
     if cpu.reg.is_set(Flags::Zero) {
         CALL_nn(cpu);
+    } else {
+        // skip the address immediate
+        cpu.reg.pc = cpu.reg.pc.wrapping_add(2);
     }
     12
 }
@@ -120,6 +126,9 @@ pub fn CALL_Z_nn(cpu: &mut CPU) -> i32 {
 pub fn CALL_NC_nn(cpu: &mut CPU) -> i32 {
     if !cpu.reg.is_set(Flags::Carry) {
         CALL_nn(cpu);
+    } else {
+        // skip the address immediate
+        cpu.reg.pc = cpu.reg.pc.wrapping_add(2);
     }
     12
 }
@@ -128,6 +137,9 @@ pub fn CALL_NC_nn(cpu: &mut CPU) -> i32 {
 pub fn CALL_C_nn(cpu: &mut CPU) -> i32 {
     if cpu.reg.is_set(Flags::Carry) {
         CALL_nn(cpu);
+    } else {
+        // skip the address immediate
+        cpu.reg.pc = cpu.reg.pc.wrapping_add(2);
     }
     12
 }
@@ -188,25 +200,25 @@ pub fn RET(cpu: &mut CPU) -> i32 {
 
 // 'RET NZ' C0 8
 pub fn RET_NZ(cpu: &mut CPU) -> i32 {
-    cpu.return_flag(Flags::Zero);
+    cpu.return_not_flag(Flags::Zero);
     8
 }
 
 // 'RET Z' C8 8
 pub fn RET_Z(cpu: &mut CPU) -> i32 {
-    cpu.return_not_flag(Flags::Zero);
+    cpu.return_flag(Flags::Zero);
     8
 }
 
 // 'RET NC' D0 8
 pub fn RET_NC(cpu: &mut CPU) -> i32 {
-    cpu.return_flag(Flags::Carry);
+    cpu.return_not_flag(Flags::Carry);
     8
 }
 
 // 'RET C' D8 8
 pub fn RET_C(cpu: &mut CPU) -> i32 {
-    cpu.return_not_flag(Flags::Carry);
+    cpu.return_flag(Flags::Carry);
     8
 }
 
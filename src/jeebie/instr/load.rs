@@ -435,7 +435,7 @@ pub fn LD_a_HLm(cpu: &mut CPU) -> i32 {
 // 'LD A,(nn)' FA 16
 pub fn LD_a_nnm(cpu: &mut CPU) -> i32 {
     cpu.load_rr(A, RegisterAddress(NN));
-    6
+    16
 }
 
 // 'LD A,n' 3E 8
@@ -459,7 +459,7 @@ pub fn LD_DEm_A(cpu: &mut CPU) -> i32 {
 // 'LD (nn),A' EA 16
 pub fn LD_nnm_A(cpu: &mut CPU) -> i32 {
     cpu.load_rr(RegisterAddress(NN), A);
-    6
+    16
 }
 
 // 'LD A,($FF00 + C)' F2 8
@@ -552,8 +552,7 @@ pub fn LD_sp_hl(cpu: &mut CPU) -> i32 {
 
 // 'LD HL,SP+n' F8 12
 pub fn LDHL_sp_n(cpu: &mut CPU) -> i32 {
-    let spn = cpu.get16(SP).wrapping_add(cpu.get8(N) as u16);
-    cpu.compute_add16(HL, Value16(spn));
+    cpu.compute_add_sp(HL, N);
     12
 }
 
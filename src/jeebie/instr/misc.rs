@@ -6,6 +6,42 @@ use jeebie::core::registers::Register16::*;
 // 'NOP' 00 4
 pub fn nop(cpu: &mut CPU) -> i32 { 4 }
 
+// 'DAA' 27 4
+pub fn DAA(cpu: &mut CPU) -> i32 {
+    cpu.compute_daa();
+    4
+}
+
+// 'CPL' 2F 4
+pub fn CPL(cpu: &mut CPU) -> i32 {
+    cpu.compute_cpl();
+    4
+}
+
+// 'SCF' 37 4
+pub fn SCF(cpu: &mut CPU) -> i32 {
+    cpu.compute_scf();
+    4
+}
+
+// 'CCF' 3F 4
+pub fn CCF(cpu: &mut CPU) -> i32 {
+    cpu.compute_ccf();
+    4
+}
+
+// 'HALT' 76 4
+pub fn HALT(cpu: &mut CPU) -> i32 {
+    cpu.halt();
+    4
+}
+
+// 'STOP' 10 4
+pub fn STOP(cpu: &mut CPU) -> i32 {
+    cpu.stop();
+    4
+}
+
 // 'SWAP A' CB 37 8
 pub fn SWAP_a(cpu: &mut CPU) -> i32 {
     cpu.compute_swap(A);
@@ -56,12 +92,80 @@ pub fn SWAP_hl(cpu: &mut CPU) -> i32 {
 
 // 'EI' FB 4
 pub fn EI(cpu: &mut CPU) -> i32 {
-    cpu.interrupts_enabled = true;
+    cpu.enable_interrupts();
     4
 }
 
 // 'DI' F3 4
 pub fn DI(cpu: &mut CPU) -> i32 {
-    cpu.interrupts_enabled = false;
+    cpu.disable_interrupts();
     4
-}
\ No newline at end of file
+}
+
+// *** Illegal opcodes, these lock up the CPU ***
+
+// 'ILLEGAL' D3 4
+pub fn ILLEGAL_d3(cpu: &mut CPU) -> i32 {
+    cpu.lock();
+    4
+}
+
+// 'ILLEGAL' DB 4
+pub fn ILLEGAL_db(cpu: &mut CPU) -> i32 {
+    cpu.lock();
+    4
+}
+
+// 'ILLEGAL' DD 4
+pub fn ILLEGAL_dd(cpu: &mut CPU) -> i32 {
+    cpu.lock();
+    4
+}
+
+// 'ILLEGAL' E3 4
+pub fn ILLEGAL_e3(cpu: &mut CPU) -> i32 {
+    cpu.lock();
+    4
+}
+
+// 'ILLEGAL' E4 4
+pub fn ILLEGAL_e4(cpu: &mut CPU) -> i32 {
+    cpu.lock();
+    4
+}
+
+// 'ILLEGAL' EB 4
+pub fn ILLEGAL_eb(cpu: &mut CPU) -> i32 {
+    cpu.lock();
+    4
+}
+
+// 'ILLEGAL' EC 4
+pub fn ILLEGAL_ec(cpu: &mut CPU) -> i32 {
+    cpu.lock();
+    4
+}
+
+// 'ILLEGAL' ED 4
+pub fn ILLEGAL_ed(cpu: &mut CPU) -> i32 {
+    cpu.lock();
+    4
+}
+
+// 'ILLEGAL' F4 4
+pub fn ILLEGAL_f4(cpu: &mut CPU) -> i32 {
+    cpu.lock();
+    4
+}
+
+// 'ILLEGAL' FC 4
+pub fn ILLEGAL_fc(cpu: &mut CPU) -> i32 {
+    cpu.lock();
+    4
+}
+
+// 'ILLEGAL' FD 4
+pub fn ILLEGAL_fd(cpu: &mut CPU) -> i32 {
+    cpu.lock();
+    4
+}
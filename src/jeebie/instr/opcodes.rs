@@ -23,20 +23,20 @@ pub static OPCODE_TABLE : [OpcodeFunc; 256] = [
         LD_nnm_sp,     ADD_hl_bc,      LD_a_BCm,        DEC_bc,
             INC_c,         DEC_c,        LD_C_n,          RRCA,
     // 0x10
-          missing,      LD_de_nn,      LD_DEm_A,        INC_de,
+             STOP,      LD_de_nn,      LD_DEm_A,        INC_de,
             INC_d,         DEC_d,        LD_D_n,           RLA,
              JR_n,     ADD_hl_de,      LD_a_DEm,        DEC_de,
             INC_e,         DEC_e,        LD_E_n,           RRA,
     // 0x20
           JR_NZ_n,      LD_hl_nn,     LDI_HLm_a,        INC_hl,
-            INC_h,         DEC_h,        LD_H_n,       missing,
+            INC_h,         DEC_h,        LD_H_n,           DAA,
            JR_Z_n,     ADD_hl_hl,     LDI_a_HLm,        DEC_hl,
-            INC_l,         DEC_l,        LD_L_n,       missing,
+            INC_l,         DEC_l,        LD_L_n,           CPL,
     // 0x30
           JR_NC_n,      LD_sp_nn,     LDD_HLm_a,        INC_sp,
-          INC_hlm,       DEC_hlm,      LD_HLm_n,       missing,
+          INC_hlm,       DEC_hlm,      LD_HLm_n,           SCF,
            JR_C_n,     ADD_hl_sp,     LDD_a_HLm,        DEC_sp,
-            INC_a,         DEC_a,        LD_a_n,       missing,
+            INC_a,         DEC_a,        LD_a_n,           CCF,
     // 0x40
            LD_b_b,        LD_b_c,        LD_b_d,        LD_b_e,
            LD_b_h,        LD_b_l,      LD_b_HLm,        LD_b_a,
@@ -54,7 +54,7 @@ pub static OPCODE_TABLE : [OpcodeFunc; 256] = [
            LD_l_h,        LD_l_l,      LD_l_HLm,        LD_l_a,
     // 0x70
          LD_HLm_b,      LD_HLm_c,      LD_HLm_d,      LD_HLm_e,
-         LD_HLm_h,      LD_HLm_l,       missing,      LD_HLm_a,
+         LD_HLm_h,      LD_HLm_l,          HALT,      LD_HLm_a,
            LD_a_b,        LD_a_c,        LD_a_d,        LD_a_e,
            LD_a_h,        LD_a_l,      LD_a_HLm,        LD_a_a,
     // 0x80
@@ -83,20 +83,20 @@ pub static OPCODE_TABLE : [OpcodeFunc; 256] = [
             RET_Z,           RET,       JP_Z_nn,       missing,
         CALL_Z_nn,       CALL_nn,       ADC_a_n,       RST_08h,
     // 0xd0
-           RET_NC,        POP_de,      JP_NC_nn,       missing,
+           RET_NC,        POP_de,      JP_NC_nn,    ILLEGAL_d3,
        CALL_NC_nn,       PUSH_de,       SUB_a_n,       RST_10h,
-            RET_C,          RETI,       JP_C_nn,       missing,
-        CALL_C_nn,       missing,       SBC_a_n,       RST_18h,
+            RET_C,          RETI,       JP_C_nn,    ILLEGAL_db,
+        CALL_C_nn,    ILLEGAL_dd,       SBC_a_n,       RST_18h,
     // 0xe0
-         LDH_nm_a,        POP_hl,    LD_c_mem_a,       missing,
-          missing,       PUSH_hl,         AND_n,       RST_20h,
-         ADD_sp_n,         JP_HL,      LD_nnm_A,       missing,
-          missing,       missing,         XOR_n,       RST_28h,
+         LDH_nm_a,        POP_hl,    LD_c_mem_a,    ILLEGAL_e3,
+       ILLEGAL_e4,       PUSH_hl,         AND_n,       RST_20h,
+         ADD_sp_n,         JP_HL,      LD_nnm_A,    ILLEGAL_eb,
+       ILLEGAL_ec,    ILLEGAL_ed,         XOR_n,       RST_28h,
     // 0xf0
          LDH_a_nm,        POP_af,    LD_a_c_mem,            DI,
-          missing,       PUSH_af,          OR_n,       RST_30h,
+       ILLEGAL_f4,       PUSH_af,          OR_n,       RST_30h,
         LDHL_sp_n,      LD_sp_hl,      LD_a_nnm,            EI,
-          missing,       missing,          CP_n,       RST_38h,
+       ILLEGAL_fc,    ILLEGAL_fd,          CP_n,       RST_38h,
 ];
 
 pub static CB_OPCODE_TABLE : [OpcodeFunc; 256] = [
@@ -0,0 +1,106 @@
+//! The interrupt controller.
+//! Holds the IE (0xFFFF) and IF (0xFF0F) registers, peripherals use it to request interrupts and
+//! the CPU reads it to know which interrupts need to be serviced.
+
+/// The interrupt sources, in priority order (highest first).
+/// The value of each variant is the bit used for it in the IE and IF registers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interrupt {
+    VBlank = 0x01,
+    LCDStat = 0x02,
+    Timer = 0x04,
+    Serial = 0x08,
+    Joypad = 0x10,
+}
+
+impl Interrupt {
+    /// Returns the address the CPU jumps to when servicing the interrupt.
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x40,
+            Interrupt::LCDStat => 0x48,
+            Interrupt::Timer => 0x50,
+            Interrupt::Serial => 0x58,
+            Interrupt::Joypad => 0x60,
+        }
+    }
+
+    /// Returns the interrupt with the highest priority among the bits set in `flags`.
+    pub fn from_flags(flags: u8) -> Option<Interrupt> {
+        [Interrupt::VBlank, Interrupt::LCDStat, Interrupt::Timer, Interrupt::Serial, Interrupt::Joypad]
+            .iter()
+            .find(|i| flags & (**i as u8) != 0)
+            .cloned()
+    }
+}
+
+pub struct InterruptController {
+    // IE register, all 8 bits are writable even if only 5 are used
+    enable: u8,
+    // IF register, only the 5 low bits are used
+    flag: u8,
+}
+
+impl InterruptController {
+    pub fn new() -> InterruptController {
+        InterruptController { enable: 0, flag: 0 }
+    }
+
+    /// Requests an interrupt, setting its bit in IF.
+    pub fn request(&mut self, interrupt: Interrupt) {
+        self.flag |= interrupt as u8;
+    }
+
+    /// Acknowledges an interrupt, clearing its bit in IF.
+    pub fn acknowledge(&mut self, interrupt: Interrupt) {
+        self.flag &= !(interrupt as u8);
+    }
+
+    /// Returns the interrupts that are both requested (IF) and enabled (IE).
+    pub fn pending(&self) -> u8 {
+        self.enable & self.flag & 0x1F
+    }
+
+    pub fn read_enable(&self) -> u8 {
+        self.enable
+    }
+
+    pub fn write_enable(&mut self, data: u8) {
+        self.enable = data;
+    }
+
+    /// Reads IF, unused bits always read as 1.
+    pub fn read_flag(&self) -> u8 {
+        self.flag | 0xE0
+    }
+
+    pub fn write_flag(&mut self, data: u8) {
+        self.flag = data & 0x1F;
+    }
+}
+
+#[test]
+fn priority_test() {
+    assert_eq!(None, Interrupt::from_flags(0));
+    assert_eq!(Some(Interrupt::VBlank), Interrupt::from_flags(0x1F));
+    assert_eq!(Some(Interrupt::Timer), Interrupt::from_flags(0x1C));
+    assert_eq!(Some(Interrupt::Joypad), Interrupt::from_flags(0x10));
+    // unused bits are ignored
+    assert_eq!(None, Interrupt::from_flags(0xE0));
+}
+
+#[test]
+fn pending_test() {
+    let mut ic = InterruptController::new();
+
+    ic.request(Interrupt::Timer);
+    assert_eq!(0xE4, ic.read_flag());
+    assert_eq!(0, ic.pending());
+
+    ic.write_enable(0xFF);
+    assert_eq!(0x04, ic.pending());
+
+    ic.acknowledge(Interrupt::Timer);
+    assert_eq!(0xE0, ic.read_flag());
+    assert_eq!(0, ic.pending());
+}
@@ -0,0 +1,156 @@
+//! The joypad, mapped to the P1/JOYP register (0xFF00).
+//!
+//! The 8 buttons are arranged in a 2x4 matrix: bit 4 of P1 selects the direction keys and bit 5
+//! the action keys, the selected group can then be read from bits 0-3.
+//! A selected line that is low (0) means the button is pressed.
+use jeebie::interrupt::{Interrupt, InterruptController};
+
+/// The buttons available on the gameboy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    /// Returns the bit used in P1 for this button.
+    fn line(self) -> u8 {
+        match self {
+            Button::Right | Button::A => 0x01,
+            Button::Left | Button::B => 0x02,
+            Button::Up | Button::Select => 0x04,
+            Button::Down | Button::Start => 0x08,
+        }
+    }
+
+    fn is_direction(self) -> bool {
+        match self {
+            Button::Right | Button::Left | Button::Up | Button::Down => true,
+            _ => false,
+        }
+    }
+}
+
+pub struct Joypad {
+    // pressed buttons, bits set to 1 are pressed (inverted with respect to P1)
+    directions: u8,
+    actions: u8,
+    // bits 4 and 5 of P1, a 0 selects the group
+    select: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            directions: 0,
+            actions: 0,
+            select: 0x30,
+        }
+    }
+
+    /// Presses a button, requesting a joypad interrupt if one of the selected lines goes low.
+    pub fn press(&mut self, button: Button, interrupts: &mut InterruptController) {
+        let old_lines = self.lines();
+
+        if button.is_direction() {
+            self.directions |= button.line();
+        } else {
+            self.actions |= button.line();
+        }
+
+        self.check_transition(old_lines, interrupts);
+    }
+
+    /// Releases a button.
+    pub fn release(&mut self, button: Button) {
+        if button.is_direction() {
+            self.directions &= !button.line();
+        } else {
+            self.actions &= !button.line();
+        }
+    }
+
+    pub fn read_register(&self) -> u8 {
+        // unused bits read as 1
+        0xC0 | self.select | self.lines()
+    }
+
+    /// Writes the select bits, only bits 4 and 5 are writable.
+    /// Selecting a group with buttons already held can also request an interrupt.
+    pub fn write_register(&mut self, data: u8, interrupts: &mut InterruptController) {
+        let old_lines = self.lines();
+        self.select = data & 0x30;
+        self.check_transition(old_lines, interrupts);
+    }
+
+    /// Returns the state of the 4 input lines (0 means pressed) for the selected groups.
+    fn lines(&self) -> u8 {
+        let mut pressed = 0;
+
+        if self.select & 0x10 == 0 {
+            pressed |= self.directions;
+        }
+        if self.select & 0x20 == 0 {
+            pressed |= self.actions;
+        }
+
+        !pressed & 0x0F
+    }
+
+    /// Requests an interrupt if any line went from high to low.
+    fn check_transition(&self, old_lines: u8, interrupts: &mut InterruptController) {
+        if old_lines & !self.lines() != 0 {
+            interrupts.request(Interrupt::Joypad);
+        }
+    }
+}
+
+#[test]
+fn select_groups_test() {
+    let mut joypad = Joypad::new();
+    let mut ic = InterruptController::new();
+
+    joypad.press(Button::Up, &mut ic);
+    joypad.press(Button::A, &mut ic);
+
+    // nothing selected, all lines high
+    assert_eq!(0xFF, joypad.read_register());
+
+    // directions
+    joypad.write_register(0x20, &mut ic);
+    assert_eq!(0xEB, joypad.read_register());
+
+    // actions
+    joypad.write_register(0x10, &mut ic);
+    assert_eq!(0xDE, joypad.read_register());
+
+    joypad.release(Button::A);
+    assert_eq!(0xDF, joypad.read_register());
+}
+
+#[test]
+fn interrupt_on_press_test() {
+    let mut joypad = Joypad::new();
+    let mut ic = InterruptController::new();
+    ic.write_enable(0xFF);
+
+    // group not selected, no interrupt
+    joypad.write_register(0x20, &mut ic);
+    joypad.press(Button::A, &mut ic);
+    assert_eq!(0, ic.pending());
+
+    // direction selected, high to low transition
+    joypad.press(Button::Down, &mut ic);
+    assert_eq!(Interrupt::Joypad as u8, ic.pending());
+    ic.acknowledge(Interrupt::Joypad);
+
+    // selecting actions while A is held
+    joypad.write_register(0x10, &mut ic);
+    assert_eq!(Interrupt::Joypad as u8, ic.pending());
+}
@@ -1,4 +1,4 @@
-use super::MemoryBankController;
+use super::{copy_ram, MemoryBankController};
 
 /// MBC1 is the first MBC chip for the Gameboy. It can address a maximum of
 /// 2MB ROM (divided in 125 banks of size 16KB) and/or 32KB RAM.
@@ -7,43 +7,205 @@ use super::MemoryBankController;
 /// ROM bank. The following 16KB refer to the selected ROM bank.
 /// Banks are numbered from 0 to 0x7F (128), but bank numbers 0x20, 0x40 and
 /// 0x60 are not usable, thus the total bank number is 125.
-struct MBC1 {
+///
+/// The bank is selected with two registers:
+///     0x2000-0x3FFF   BANK1, the 5 low bits of the ROM bank. Writing 0 selects 1 instead.
+///     0x4000-0x5FFF   BANK2, 2 bits used as RAM bank or as bits 5-6 of the ROM bank.
+///     0x6000-0x7FFF   Banking mode. In mode 1, BANK2 also applies to 0x0000-0x3FFF and RAM.
+/// RAM is enabled by writing 0xA in the low nibble of any address in 0x0000-0x1FFF.
+///
+/// MBC1M multicarts wire only 4 bits of BANK1, so BANK2 selects bits 4-5 of the ROM bank.
+pub struct MBC1 {
     data: Vec<u8>,
     ram: Vec<u8>,
-    selected_rom_bank: u8,
-    selected_ram_bank: u8,
+    ram_enabled: bool,
+    bank1: u8,
+    bank2: u8,
+    advanced_mode: bool,
+    multicart: bool,
 }
 
 impl MBC1 {
-    pub fn with_data(rom_data: Vec<u8>) -> Self {
+    pub fn with_data(rom_data: Vec<u8>, ram_size: usize) -> Self {
+        let multicart = MBC1::is_multicart(&rom_data);
+
         MBC1 {
             data: rom_data,
-            ram: vec![0; 32768], // 32KB RAM
-            selected_rom_bank: 1,
-            selected_ram_bank: 0,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            advanced_mode: false,
+            multicart: multicart,
         }
     }
+
+    /// MBC1M multicarts are 1MB ROMs with a game header (and logo) in bank 0x10 too.
+    fn is_multicart(data: &[u8]) -> bool {
+        if data.len() != 0x100000 {
+            return false;
+        }
+
+        data[0x104..0x134] == data[0x40104..0x40134]
+    }
+
+    /// The amount of bits of BANK1 wired to the ROM address lines.
+    fn bank1_bits(&self) -> u8 {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    /// The ROM bank mapped at 0x0000-0x3FFF.
+    fn low_rom_bank(&self) -> usize {
+        if self.advanced_mode {
+            (self.bank2 << self.bank1_bits()) as usize
+        } else {
+            0
+        }
+    }
+
+    /// The ROM bank mapped at 0x4000-0x7FFF.
+    fn high_rom_bank(&self) -> usize {
+        let mask = (1 << self.bank1_bits()) - 1;
+        ((self.bank2 << self.bank1_bits()) | (self.bank1 & mask)) as usize
+    }
+
+    fn ram_address(&self, addr: u16) -> usize {
+        let bank = if self.advanced_mode { self.bank2 as usize } else { 0 };
+        (bank * 0x2000 + (addr - 0xA000) as usize) % self.ram.len()
+    }
+
+    fn rom_address(&self, bank: usize, addr: u16) -> usize {
+        (bank * 0x4000 + (addr & 0x3FFF) as usize) % self.data.len()
+    }
 }
 
 impl MemoryBankController for MBC1 {
 
     fn read(&self, addr: u16) -> u8 {
         match addr {
-            0..=0x3FFF => self.data[addr as usize], // bank 0
-            0x4000..=0x7FFF => {
-                // the selected bank data (banks 1 to 0x7F)
-                let base_address = self.selected_rom_bank as u16 * 0x4000;
-                self.data[(base_address + addr) as usize]
-            },
+            0..=0x3FFF => self.data[self.rom_address(self.low_rom_bank(), addr)],
+            0x4000..=0x7FFF => self.data[self.rom_address(self.high_rom_bank(), addr)],
             0xA000..=0xBFFF => {
-                // the selected RAM bank data (banks 0 to 3)
-                self.ram[(addr - 0xA000) as usize]
+                if !self.ram_enabled || self.ram.is_empty() {
+                    return 0xFF;
+                }
+
+                self.ram[self.ram_address(addr)]
             }
-            _ => panic!("RomOnly MBC attempted read at ${:04x}", addr),
+            _ => panic!("MBC1 attempted read at ${:04x}", addr),
         }
     }
 
     fn write(&mut self, addr: u16, data: u8) {
-        // TODO finish this
+        match addr {
+            0..=0x1FFF => self.ram_enabled = (data & 0x0F) == 0x0A,
+            0x2000..=0x3FFF => {
+                // the 0 -> 1 check is done on all 5 bits, even on multicarts
+                self.bank1 = data & 0x1F;
+                if self.bank1 == 0 {
+                    self.bank1 = 1;
+                }
+            },
+            0x4000..=0x5FFF => self.bank2 = data & 0x03,
+            0x6000..=0x7FFF => self.advanced_mode = (data & 0x01) == 1,
+            0xA000..=0xBFFF => {
+                if self.ram_enabled && !self.ram.is_empty() {
+                    let ram_addr = self.ram_address(addr);
+                    self.ram[ram_addr] = data;
+                }
+            },
+            _ => panic!("MBC1 attempted write at ${:04x}", addr),
+        };
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
     }
-}
\ No newline at end of file
+
+    fn load_ram(&mut self, data: &[u8]) {
+        copy_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+fn banked_rom(banks: usize) -> Vec<u8> {
+    // every byte holds the number of its bank
+    (0..banks * 0x4000).map(|i| (i / 0x4000) as u8).collect()
+}
+
+#[test]
+fn mbc1_rom_banking() {
+    let mut mbc = MBC1::with_data(banked_rom(128), 0);
+
+    assert_eq!(0, mbc.read(0x0000));
+    assert_eq!(1, mbc.read(0x4000));
+
+    mbc.write(0x2000, 0x05);
+    assert_eq!(5, mbc.read(0x7FFF));
+
+    // bank 0 selects bank 1
+    mbc.write(0x2000, 0x00);
+    assert_eq!(1, mbc.read(0x4000));
+
+    // the quirk only checks the 5 bits, bank 0x20 maps to 0x21
+    mbc.write(0x4000, 0x01);
+    assert_eq!(0x21, mbc.read(0x4000));
+    // mode 0, bank 0 is always mapped low
+    assert_eq!(0, mbc.read(0x0000));
+
+    // mode 1, BANK2 applies to the low area too
+    mbc.write(0x6000, 0x01);
+    assert_eq!(0x20, mbc.read(0x0000));
+}
+
+#[test]
+fn mbc1_rom_bank_wraps() {
+    let mut mbc = MBC1::with_data(banked_rom(4), 0);
+
+    mbc.write(0x2000, 0x05);
+    assert_eq!(1, mbc.read(0x4000));
+}
+
+#[test]
+fn mbc1_ram() {
+    let mut mbc = MBC1::with_data(banked_rom(4), 0x8000);
+
+    // disabled RAM is not writable and reads 0xFF
+    mbc.write(0xA000, 0x42);
+    assert_eq!(0xFF, mbc.read(0xA000));
+
+    mbc.write(0x0000, 0x0A);
+    mbc.write(0xA000, 0x42);
+    assert_eq!(0x42, mbc.read(0xA000));
+
+    // RAM banks are only switched in mode 1
+    mbc.write(0x4000, 0x02);
+    assert_eq!(0x42, mbc.read(0xA000));
+    mbc.write(0x6000, 0x01);
+    assert_eq!(0x00, mbc.read(0xA000));
+    mbc.write(0xA000, 0x24);
+
+    mbc.write(0x6000, 0x00);
+    assert_eq!(0x42, mbc.read(0xA000));
+
+    // any value without 0xA in the low nibble disables RAM
+    mbc.write(0x1FFF, 0x00);
+    assert_eq!(0xFF, mbc.read(0xA000));
+}
+
+#[test]
+fn mbc1_multicart() {
+    let mut rom = banked_rom(64);
+    for i in 0x104..0x134 {
+        rom[i] = i as u8;
+        rom[0x40000 + i] = i as u8;
+    }
+    let mut mbc = MBC1::with_data(rom, 0);
+
+    // BANK2 selects bits 4-5
+    mbc.write(0x4000, 0x01);
+    mbc.write(0x2000, 0x12);
+    assert_eq!(0x12, mbc.read(0x4000));
+    mbc.write(0x6000, 0x01);
+    assert_eq!(0x10, mbc.read(0x0000));
+}
@@ -0,0 +1,120 @@
+use super::{copy_ram, MemoryBankController};
+
+/// MBC2 can address up to 256KB ROM (16 banks of 16KB) and has 512x4 bits of RAM built in
+/// the controller itself.
+///
+/// Both registers are mapped in 0x0000-0x3FFF and selected by bit 8 of the address:
+///     bit 8 clear     RAM enable, by writing 0xA in the low nibble.
+///     bit 8 set       ROM bank number (4 bits). Writing 0 selects 1 instead.
+///
+/// Only the low 9 bits of the address are used for RAM, so the 512 half-bytes are echoed
+/// across the whole 0xA000-0xBFFF area. The upper nibble of RAM reads back as all 1s.
+pub struct MBC2 {
+    data: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl MBC2 {
+    pub fn with_data(rom_data: Vec<u8>) -> Self {
+        MBC2 {
+            data: rom_data,
+            ram: vec![0; 512],
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl MemoryBankController for MBC2 {
+
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0..=0x3FFF => self.data[addr as usize],
+            0x4000..=0x7FFF => {
+                let bank_addr = self.rom_bank as usize * 0x4000 + (addr - 0x4000) as usize;
+                self.data[bank_addr % self.data.len()]
+            },
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+
+                0xF0 | self.ram[(addr & 0x1FF) as usize]
+            },
+            _ => panic!("MBC2 attempted read at ${:04x}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0..=0x3FFF => {
+                if addr & 0x100 == 0 {
+                    self.ram_enabled = (data & 0x0F) == 0x0A;
+                } else {
+                    self.rom_bank = data & 0x0F;
+                    if self.rom_bank == 0 {
+                        self.rom_bank = 1;
+                    }
+                }
+            },
+            0x4000..=0x7FFF => {},
+            0xA000..=0xBFFF => {
+                if self.ram_enabled {
+                    self.ram[(addr & 0x1FF) as usize] = data & 0x0F;
+                }
+            },
+            _ => panic!("MBC2 attempted write at ${:04x}", addr),
+        };
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        copy_ram(&mut self.ram, data);
+    }
+}
+
+#[test]
+fn mbc2_rom_banking() {
+    let rom: Vec<u8> = (0..16 * 0x4000).map(|i| (i / 0x4000) as u8).collect();
+    let mut mbc = MBC2::with_data(rom);
+
+    assert_eq!(1, mbc.read(0x4000));
+
+    // bit 8 set, ROM bank
+    mbc.write(0x2100, 0x0F);
+    assert_eq!(0x0F, mbc.read(0x4000));
+    mbc.write(0x0100, 0x00);
+    assert_eq!(0x01, mbc.read(0x4000));
+
+    // bit 8 clear, RAM enable, the bank is unchanged
+    mbc.write(0x2000, 0x03);
+    assert_eq!(0x01, mbc.read(0x4000));
+}
+
+#[test]
+fn mbc2_ram() {
+    let mut mbc = MBC2::with_data(vec![0; 0x8000]);
+
+    mbc.write(0xA000, 0x05);
+    assert_eq!(0xFF, mbc.read(0xA000));
+
+    // ROM bank select, RAM is still disabled
+    mbc.write(0x010A, 0x0A);
+    assert_eq!(0xFF, mbc.read(0xA000));
+
+    mbc.write(0x000A, 0x0A);
+    mbc.write(0xA000, 0x35);
+    // only the low nibble is stored
+    assert_eq!(0xF5, mbc.read(0xA000));
+
+    // echoed every 512 bytes
+    assert_eq!(0xF5, mbc.read(0xA200));
+    assert_eq!(0xF5, mbc.read(0xBE00));
+    mbc.write(0xBFFF, 0x0C);
+    assert_eq!(0xFC, mbc.read(0xA1FF));
+}
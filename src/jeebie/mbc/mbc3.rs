@@ -0,0 +1,467 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{copy_ram, MemoryBankController};
+
+/// Size in bytes of the serialized RTC state.
+pub const RTC_STATE_SIZE: usize = 48;
+
+/// A source of time for the RTC, expressed in seconds.
+/// This allows to drive the clock with something other than the system time, e.g. in tests.
+pub trait ClockSource {
+    fn now(&self) -> u64;
+}
+
+/// The default clock source, seconds since the unix epoch.
+pub struct SystemClock;
+
+impl ClockSource for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+}
+
+/// The RTC registers, as selected by writing 0x08-0x0C to the RAM bank register.
+///     08  Seconds 0-59
+///     09  Minutes 0-59
+///     0A  Hours 0-23
+///     0B  Lower 8 bits of the day counter
+///     0C  Bit 0: bit 8 of the day counter, bit 6: halt, bit 7: day counter carry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RtcRegisters {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    pub day_low: u8,
+    pub day_high: u8,
+}
+
+impl RtcRegisters {
+    pub fn new() -> RtcRegisters {
+        RtcRegisters { seconds: 0, minutes: 0, hours: 0, day_low: 0, day_high: 0 }
+    }
+
+    fn read(&self, register: u8) -> u8 {
+        match register {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => self.day_low,
+            0x0C => self.day_high,
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, register: u8, data: u8) {
+        match register {
+            0x08 => self.seconds = data & 0x3F,
+            0x09 => self.minutes = data & 0x3F,
+            0x0A => self.hours = data & 0x1F,
+            0x0B => self.day_low = data,
+            0x0C => self.day_high = data & 0xC1,
+            _ => {},
+        };
+    }
+
+    fn halted(&self) -> bool {
+        (self.day_high & 0x40) != 0
+    }
+
+    fn days(&self) -> u64 {
+        (((self.day_high & 0x01) as u64) << 8) | self.day_low as u64
+    }
+
+    /// Advances the clock by the specified amount of seconds.
+    /// When the day counter goes past 511 it wraps and the carry bit is set.
+    fn advance(&mut self, seconds: u64) {
+        let total = seconds + self.seconds as u64 + self.minutes as u64 * 60 +
+                    self.hours as u64 * 3600 + self.days() * 86400;
+
+        let mut days = total / 86400;
+        let mut day_high = self.day_high & 0xC0;
+        if days > 0x1FF {
+            day_high |= 0x80;
+            days &= 0x1FF;
+        }
+
+        self.seconds = (total % 60) as u8;
+        self.minutes = ((total / 60) % 60) as u8;
+        self.hours = ((total / 3600) % 24) as u8;
+        self.day_low = days as u8;
+        self.day_high = day_high | (days >> 8) as u8;
+    }
+}
+
+/// The MBC3 real time clock.
+/// The registers are kept up to date lazily, they are advanced by the time elapsed from the
+/// last update whenever they are accessed.
+pub struct Rtc {
+    clock: Box<dyn ClockSource>,
+    registers: RtcRegisters,
+    latched: RtcRegisters,
+    // clock time when registers were last brought up to date
+    timestamp: u64,
+    // the last value written to the latch register
+    latch_value: u8,
+}
+
+impl Rtc {
+    pub fn new(clock: Box<dyn ClockSource>) -> Rtc {
+        let now = clock.now();
+
+        Rtc {
+            clock: clock,
+            registers: RtcRegisters::new(),
+            latched: RtcRegisters::new(),
+            timestamp: now,
+            latch_value: 0xFF,
+        }
+    }
+
+    /// Brings the registers up to date with the clock source.
+    fn update(&mut self) {
+        let now = self.clock.now();
+
+        if !self.registers.halted() && now > self.timestamp {
+            self.registers.advance(now - self.timestamp);
+        }
+
+        self.timestamp = now;
+    }
+
+    /// Writing 0 and then 1 copies the current time in the latched registers.
+    fn write_latch(&mut self, data: u8) {
+        if self.latch_value == 0 && data == 1 {
+            self.update();
+            self.latched = self.registers;
+        }
+
+        self.latch_value = data;
+    }
+
+    fn read(&self, register: u8) -> u8 {
+        self.latched.read(register)
+    }
+
+    fn write(&mut self, register: u8, data: u8) {
+        self.update();
+        self.registers.write(register, data);
+    }
+
+    /// Serializes the RTC in the 48 byte format used by BGB/SameBoy and other emulators:
+    /// the current registers and the latched registers, each as a little endian u32,
+    /// followed by the unix timestamp of the save as a little endian u64.
+    pub fn save(&mut self) -> Vec<u8> {
+        self.update();
+
+        let mut out = Vec::with_capacity(RTC_STATE_SIZE);
+        for regs in &[self.registers, self.latched] {
+            for value in &[regs.seconds, regs.minutes, regs.hours, regs.day_low, regs.day_high] {
+                out.extend_from_slice(&[*value, 0, 0, 0]);
+            }
+        }
+
+        for i in 0..8 {
+            out.push((self.timestamp >> (i * 8)) as u8);
+        }
+
+        out
+    }
+
+    /// Restores the RTC from the 48 byte format, see `save`.
+    /// The time elapsed from the save timestamp is added to the clock.
+    pub fn load(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() < RTC_STATE_SIZE {
+            return Err(format!("RTC data too short, {} bytes", data.len()));
+        }
+
+        let reg = |i: usize| data[i * 4];
+        self.registers = RtcRegisters::new();
+        self.latched = RtcRegisters::new();
+        for i in 0..5 {
+            self.registers.write(0x08 + i as u8, reg(i));
+            self.latched.write(0x08 + i as u8, reg(i + 5));
+        }
+
+        self.timestamp = (0..8).fold(0u64, |acc, i| acc | ((data[40 + i] as u64) << (i * 8)));
+        self.update();
+        Ok(())
+    }
+}
+
+/// MBC3 can address up to 2MB ROM (128 banks of 16KB) and 32KB RAM (4 banks of 8KB).
+/// Some cartridges also have a real time clock (RTC), with its registers mapped in place of RAM.
+///
+///     0x0000-0x1FFF   RAM and RTC enable, by writing 0xA in the low nibble.
+///     0x2000-0x3FFF   ROM bank number, 7 bits. Writing 0 selects 1 instead.
+///     0x4000-0x5FFF   RAM bank number (0x00-0x03) or RTC register select (0x08-0x0C).
+///     0x6000-0x7FFF   Latch clock data, by writing 0 and then 1.
+pub struct MBC3 {
+    data: Vec<u8>,
+    ram: Vec<u8>,
+    rtc: Option<Rtc>,
+    ram_enabled: bool,
+    rom_bank: u8,
+    // RAM bank or RTC register
+    ram_select: u8,
+}
+
+impl MBC3 {
+    /// Creates an MBC3 without a clock.
+    pub fn with_data(rom_data: Vec<u8>, ram_size: usize) -> Self {
+        MBC3 {
+            data: rom_data,
+            ram: vec![0; ram_size],
+            rtc: None,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+        }
+    }
+
+    /// Creates an MBC3 with an RTC driven by the provided clock source.
+    pub fn with_clock(rom_data: Vec<u8>, ram_size: usize, clock: Box<dyn ClockSource>) -> Self {
+        let mut mbc = MBC3::with_data(rom_data, ram_size);
+        mbc.rtc = Some(Rtc::new(clock));
+        mbc
+    }
+
+    /// Returns the RTC, if the cartridge has one.
+    pub fn rtc(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+
+    fn ram_address(&self, addr: u16) -> usize {
+        (self.ram_select as usize * 0x2000 + (addr - 0xA000) as usize) % self.ram.len()
+    }
+}
+
+impl MemoryBankController for MBC3 {
+
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0..=0x3FFF => self.data[addr as usize],
+            0x4000..=0x7FFF => {
+                let bank_addr = self.rom_bank as usize * 0x4000 + (addr - 0x4000) as usize;
+                self.data[bank_addr % self.data.len()]
+            },
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+
+                match (self.ram_select, &self.rtc) {
+                    (0x00..=0x03, _) if !self.ram.is_empty() => self.ram[self.ram_address(addr)],
+                    (0x08..=0x0C, Some(rtc)) => rtc.read(self.ram_select),
+                    _ => 0xFF,
+                }
+            },
+            _ => panic!("MBC3 attempted read at ${:04x}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0..=0x1FFF => self.ram_enabled = (data & 0x0F) == 0x0A,
+            0x2000..=0x3FFF => {
+                self.rom_bank = data & 0x7F;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            },
+            0x4000..=0x5FFF => self.ram_select = data & 0x0F,
+            0x6000..=0x7FFF => {
+                if let Some(ref mut rtc) = self.rtc {
+                    rtc.write_latch(data);
+                }
+            },
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return;
+                }
+
+                match self.ram_select {
+                    0x00..=0x03 if !self.ram.is_empty() => {
+                        let ram_addr = self.ram_address(addr);
+                        self.ram[ram_addr] = data;
+                    },
+                    0x08..=0x0C => {
+                        if let Some(ref mut rtc) = self.rtc {
+                            rtc.write(self.ram_select, data);
+                        }
+                    },
+                    _ => {},
+                }
+            },
+            _ => panic!("MBC3 attempted write at ${:04x}", addr),
+        };
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        copy_ram(&mut self.ram, data);
+    }
+
+    fn rtc_state(&mut self) -> Option<Vec<u8>> {
+        self.rtc.as_mut().map(|rtc| rtc.save())
+    }
+
+    fn load_rtc_state(&mut self, data: &[u8]) -> Result<(), String> {
+        match self.rtc {
+            Some(ref mut rtc) => rtc.load(data),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+use std::rc::Rc;
+#[cfg(test)]
+use std::cell::Cell;
+
+/// A clock source controlled by tests.
+#[cfg(test)]
+struct TestClock(Rc<Cell<u64>>);
+
+#[cfg(test)]
+impl ClockSource for TestClock {
+    fn now(&self) -> u64 {
+        self.0.get()
+    }
+}
+
+#[cfg(test)]
+fn rtc_mbc(time: &Rc<Cell<u64>>) -> MBC3 {
+    let mut mbc = MBC3::with_clock(vec![0; 0x8000], 0x2000, Box::new(TestClock(time.clone())));
+    mbc.write(0x0000, 0x0A);
+    mbc
+}
+
+#[cfg(test)]
+fn latch(mbc: &mut MBC3) {
+    mbc.write(0x6000, 0x00);
+    mbc.write(0x6000, 0x01);
+}
+
+#[cfg(test)]
+fn read_rtc(mbc: &mut MBC3, register: u8) -> u8 {
+    mbc.write(0x4000, register);
+    mbc.read(0xA000)
+}
+
+#[test]
+fn mbc3_rom_banking() {
+    let rom: Vec<u8> = (0..128 * 0x4000).map(|i| (i / 0x4000) as u8).collect();
+    let mut mbc = MBC3::with_data(rom, 0);
+
+    assert_eq!(1, mbc.read(0x4000));
+    mbc.write(0x2000, 0x7F);
+    assert_eq!(0x7F, mbc.read(0x7FFF));
+    mbc.write(0x2000, 0x00);
+    assert_eq!(1, mbc.read(0x4000));
+    // unlike MBC1, bank 0x20 is accessible
+    mbc.write(0x2000, 0x20);
+    assert_eq!(0x20, mbc.read(0x4000));
+}
+
+#[test]
+fn mbc3_ram_banking() {
+    let mut mbc = MBC3::with_data(vec![0; 0x8000], 0x8000);
+
+    mbc.write(0xA000, 0x42);
+    assert_eq!(0xFF, mbc.read(0xA000));
+
+    mbc.write(0x0000, 0x0A);
+    mbc.write(0x4000, 0x03);
+    mbc.write(0xA000, 0x42);
+    assert_eq!(0x42, mbc.read(0xA000));
+    mbc.write(0x4000, 0x00);
+    assert_eq!(0x00, mbc.read(0xA000));
+}
+
+#[test]
+fn mbc3_rtc_latch() {
+    let time = Rc::new(Cell::new(1000));
+    let mut mbc = rtc_mbc(&time);
+
+    time.set(1000 + 61);
+    // not latched yet
+    assert_eq!(0, read_rtc(&mut mbc, 0x08));
+
+    latch(&mut mbc);
+    assert_eq!(1, read_rtc(&mut mbc, 0x08));
+    assert_eq!(1, read_rtc(&mut mbc, 0x09));
+
+    // latched values don't change until the next latch
+    time.set(1000 + 62);
+    assert_eq!(1, read_rtc(&mut mbc, 0x08));
+    latch(&mut mbc);
+    assert_eq!(2, read_rtc(&mut mbc, 0x08));
+}
+
+#[test]
+fn mbc3_rtc_days_and_carry() {
+    let time = Rc::new(Cell::new(0));
+    let mut mbc = rtc_mbc(&time);
+
+    time.set(300 * 86400 + 3600 * 5);
+    latch(&mut mbc);
+    assert_eq!(5, read_rtc(&mut mbc, 0x0A));
+    assert_eq!(300 & 0xFF, read_rtc(&mut mbc, 0x0B) as u32);
+    assert_eq!(0x01, read_rtc(&mut mbc, 0x0C));
+
+    // past 511 days the counter wraps and the carry is set
+    time.set(513 * 86400);
+    latch(&mut mbc);
+    assert_eq!(1, read_rtc(&mut mbc, 0x0B));
+    assert_eq!(0x80, read_rtc(&mut mbc, 0x0C));
+}
+
+#[test]
+fn mbc3_rtc_halt_and_write() {
+    let time = Rc::new(Cell::new(0));
+    let mut mbc = rtc_mbc(&time);
+
+    // halt the clock and set the time
+    mbc.write(0x4000, 0x0C);
+    mbc.write(0xA000, 0x40);
+    mbc.write(0x4000, 0x09);
+    mbc.write(0xA000, 30);
+
+    time.set(500);
+    latch(&mut mbc);
+    assert_eq!(0, read_rtc(&mut mbc, 0x08));
+    assert_eq!(30, read_rtc(&mut mbc, 0x09));
+
+    // restart the clock
+    mbc.write(0x4000, 0x0C);
+    mbc.write(0xA000, 0x00);
+    time.set(510);
+    latch(&mut mbc);
+    assert_eq!(10, read_rtc(&mut mbc, 0x08));
+}
+
+#[test]
+fn mbc3_rtc_save_load() {
+    let time = Rc::new(Cell::new(100));
+    let mut mbc = rtc_mbc(&time);
+
+    time.set(100 + 3661);
+    latch(&mut mbc);
+    let state = mbc.rtc().unwrap().save();
+    assert_eq!(RTC_STATE_SIZE, state.len());
+
+    // one more minute passes while the emulator is closed
+    time.set(100 + 3661 + 60);
+    let mut other = rtc_mbc(&time);
+    other.rtc().unwrap().load(&state).unwrap();
+
+    // latched registers are restored as they were
+    assert_eq!(1, read_rtc(&mut other, 0x08));
+    latch(&mut other);
+    assert_eq!(1, read_rtc(&mut other, 0x08));
+    assert_eq!(2, read_rtc(&mut other, 0x09));
+    assert_eq!(1, read_rtc(&mut other, 0x0A));
+}
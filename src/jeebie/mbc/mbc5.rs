@@ -0,0 +1,178 @@
+use super::{copy_ram, MemoryBankController, RumbleCallback};
+
+/// MBC5 can address up to 8MB ROM (512 banks of 16KB) and 128KB RAM (16 banks of 8KB).
+/// It's the first MBC guaranteed to work with the CGB double speed mode.
+///
+///     0x0000-0x1FFF   RAM enable, by writing 0xA in the low nibble.
+///     0x2000-0x2FFF   Low 8 bits of the ROM bank number. Bank 0 can be selected too.
+///     0x3000-0x3FFF   Bit 8 of the ROM bank number.
+///     0x4000-0x5FFF   RAM bank number (4 bits).
+///
+/// On rumble cartridges bit 3 of the RAM bank register drives the rumble motor instead,
+/// leaving only 3 bits for the RAM bank.
+pub struct MBC5 {
+    data: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+    rumble: bool,
+    rumble_callback: Option<RumbleCallback>,
+}
+
+impl MBC5 {
+    pub fn with_data(rom_data: Vec<u8>, ram_size: usize, has_rumble: bool) -> Self {
+        MBC5 {
+            data: rom_data,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble: has_rumble,
+            rumble: false,
+            rumble_callback: None,
+        }
+    }
+
+    /// Returns true if the rumble motor is on.
+    pub fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    fn ram_address(&self, addr: u16) -> usize {
+        (self.ram_bank as usize * 0x2000 + (addr - 0xA000) as usize) % self.ram.len()
+    }
+
+    fn write_ram_bank(&mut self, data: u8) {
+        if !self.has_rumble {
+            self.ram_bank = data & 0x0F;
+            return;
+        }
+
+        self.ram_bank = data & 0x07;
+
+        let rumble = (data & 0x08) != 0;
+        if rumble != self.rumble {
+            self.rumble = rumble;
+
+            if let Some(ref mut callback) = self.rumble_callback {
+                callback(rumble);
+            }
+        }
+    }
+}
+
+impl MemoryBankController for MBC5 {
+
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0..=0x3FFF => self.data[addr as usize],
+            0x4000..=0x7FFF => {
+                let bank_addr = self.rom_bank as usize * 0x4000 + (addr - 0x4000) as usize;
+                self.data[bank_addr % self.data.len()]
+            },
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled || self.ram.is_empty() {
+                    return 0xFF;
+                }
+
+                self.ram[self.ram_address(addr)]
+            },
+            _ => panic!("MBC5 attempted read at ${:04x}", addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0..=0x1FFF => self.ram_enabled = (data & 0x0F) == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | data as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | (((data & 0x01) as u16) << 8),
+            0x4000..=0x5FFF => self.write_ram_bank(data),
+            0x6000..=0x7FFF => {},
+            0xA000..=0xBFFF => {
+                if self.ram_enabled && !self.ram.is_empty() {
+                    let ram_addr = self.ram_address(addr);
+                    self.ram[ram_addr] = data;
+                }
+            },
+            _ => panic!("MBC5 attempted write at ${:04x}", addr),
+        };
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        copy_ram(&mut self.ram, data);
+    }
+
+    fn set_rumble_callback(&mut self, callback: RumbleCallback) {
+        self.rumble_callback = Some(callback);
+    }
+}
+
+#[test]
+fn mbc5_rom_banking() {
+    // every 16KB bank starts with its bank number (low and high byte)
+    let mut rom = vec![0; 512 * 0x4000];
+    for bank in 0..512 {
+        rom[bank * 0x4000] = bank as u8;
+        rom[bank * 0x4000 + 1] = (bank >> 8) as u8;
+    }
+    let mut mbc = MBC5::with_data(rom, 0, false);
+
+    assert_eq!(1, mbc.read(0x4000));
+
+    // bank 0 is not remapped
+    mbc.write(0x2000, 0x00);
+    assert_eq!(0, mbc.read(0x4000));
+
+    mbc.write(0x2000, 0xFF);
+    mbc.write(0x3000, 0x01);
+    assert_eq!(0xFF, mbc.read(0x4000));
+    assert_eq!(0x01, mbc.read(0x4001));
+
+    mbc.write(0x2000, 0x02);
+    assert_eq!(0x02, mbc.read(0x4000));
+    assert_eq!(0x01, mbc.read(0x4001));
+}
+
+#[test]
+fn mbc5_ram_banking() {
+    let mut mbc = MBC5::with_data(vec![0; 0x8000], 0x20000, false);
+
+    mbc.write(0x0000, 0x0A);
+    mbc.write(0x4000, 0x0F);
+    mbc.write(0xA000, 0x42);
+    assert_eq!(0x42, mbc.read(0xA000));
+
+    mbc.write(0x4000, 0x07);
+    assert_eq!(0x00, mbc.read(0xA000));
+}
+
+#[test]
+fn mbc5_rumble() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    let events = Rc::new(RefCell::new(vec![]));
+    let mut mbc = MBC5::with_data(vec![0; 0x8000], 0x8000, true);
+
+    let callback_events = events.clone();
+    mbc.set_rumble_callback(Box::new(move |on| callback_events.borrow_mut().push(on)));
+
+    mbc.write(0x0000, 0x0A);
+    mbc.write(0x4000, 0x09);
+    assert!(mbc.rumble());
+    // bit 3 doesn't select the RAM bank
+    mbc.write(0xA000, 0x42);
+    mbc.write(0x4000, 0x01);
+    assert!(!mbc.rumble());
+    assert_eq!(0x42, mbc.read(0xA000));
+
+    // only changes are notified
+    mbc.write(0x4000, 0x00);
+    assert_eq!(vec![true, false], *events.borrow());
+}
@@ -1,5 +1,17 @@
 pub mod nombc;
 pub mod mbc1;
+pub mod mbc2;
+pub mod mbc3;
+pub mod mbc5;
+
+use jeebie::cart::Cartridge;
+use self::mbc1::MBC1;
+use self::mbc2::MBC2;
+use self::mbc3::{MBC3, SystemClock};
+use self::mbc5::MBC5;
+
+/// A function called when the state of the rumble motor changes (true means on).
+pub type RumbleCallback = Box<dyn FnMut(bool)>;
 
 /// A MemoryBankController (MBC) is the interface used to read/write
 /// data on a gameboy cartridge.
@@ -8,4 +20,49 @@ pub mod mbc1;
 pub trait MemoryBankController {
     fn read(&self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, data: u8);
+
+    /// Returns the external RAM, this is what gets persisted for battery backed cartridges.
+    fn ram(&self) -> &[u8];
+
+    /// Replaces the content of the external RAM, extra data is ignored.
+    fn load_ram(&mut self, data: &[u8]);
+
+    /// Returns the serialized RTC state, for MBCs that have a clock.
+    fn rtc_state(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Restores the RTC state. This does nothing for MBCs without a clock.
+    fn load_rtc_state(&mut self, data: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Sets a function to be called when the rumble motor is turned on or off.
+    /// This does nothing for MBCs without rumble.
+    fn set_rumble_callback(&mut self, callback: RumbleCallback) {}
+}
+
+/// Copies as much of `data` as fits in `ram`, used when loading saves.
+pub fn copy_ram(ram: &mut [u8], data: &[u8]) {
+    let len = ram.len().min(data.len());
+    ram[..len].copy_from_slice(&data[..len]);
+}
+
+/// Creates the MemoryBankController for a cartridge, using the cartridge type in its header.
+/// The amount of external RAM is given by the RAM size in the header.
+pub fn from_cartridge(cart: &Cartridge) -> Result<Box<dyn MemoryBankController>, String> {
+    use jeebie::cart::header::CartridgeType::*;
+
+    let ram_size = cart.header.ram_size.bytes();
+
+    match cart.header.cartridge_type {
+        RomOnly | RomRam | RomRamBattery => Ok(Box::new(nombc::RomOnly::with_data(cart.data.clone()))),
+        Mbc1 | Mbc1Ram | Mbc1RamBattery => Ok(Box::new(MBC1::with_data(cart.data.clone(), ram_size))),
+        Mbc2 | Mbc2Battery => Ok(Box::new(MBC2::with_data(cart.data.clone()))),
+        Mbc3TimerBattery | Mbc3TimerRamBattery => Ok(Box::new(MBC3::with_clock(cart.data.clone(), ram_size, Box::new(SystemClock)))),
+        Mbc3 | Mbc3Ram | Mbc3RamBattery => Ok(Box::new(MBC3::with_data(cart.data.clone(), ram_size))),
+        Mbc5 | Mbc5Ram | Mbc5RamBattery => Ok(Box::new(MBC5::with_data(cart.data.clone(), ram_size, false))),
+        Mbc5Rumble | Mbc5RumbleRam | Mbc5RumbleRamBattery => Ok(Box::new(MBC5::with_data(cart.data.clone(), ram_size, true))),
+        other => Err(format!("Unsupported cartridge type {:?}", other)),
+    }
 }
@@ -1,4 +1,4 @@
-use super::MemoryBankController;
+use super::{copy_ram, MemoryBankController};
 
 /// RomOnly is the simplest MBC, as in, there's actually no controller at all.
 /// This maps the ROM directly to addresses 0x0000 to 0x7FFF
@@ -46,6 +46,14 @@ impl MemoryBankController for RomOnly {
             _ => panic!("RomOnly MBC attempted write at ${:04x}", addr),
         };
     }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        copy_ram(&mut self.ram, data);
+    }
 }
 
 #[test]
@@ -3,21 +3,47 @@
 //! The MMU acts as the system bus, allowing components to communicate with each other, reaches
 //! RAM, ROM, I/O registers and more.
 use std::fmt;
-use std::cell::Cell;
 
 use jeebie::video::gpu::GPU;
 use jeebie::cart::Cartridge;
-use jeebie::mbc::MemoryBankController;
+#[cfg(test)]
+use jeebie::cart::header::{NINTENDO_LOGO, header_checksum};
+use jeebie::interrupt::InterruptController;
+use jeebie::timer::Timer;
+use jeebie::serial::Serial;
+use jeebie::apu::APU;
+use jeebie::joypad::Joypad;
+use jeebie::mbc;
+use jeebie::mbc::{MemoryBankController, RumbleCallback};
 use jeebie::mbc::nombc::RomOnly;
-use jeebie::bootrom::DMG_BOOTROM;
+use jeebie::mbc::mbc3::RTC_STATE_SIZE;
+use jeebie::bootrom::{BootRom, Model};
+
+/// An OAM DMA transfer in progress, started by a write to 0xFF46.
+/// One byte is copied every 4 cycles, after 4 cycles spent setting up the transfer.
+struct DmaTransfer {
+    source: u16,
+    offset: u16,
+    cycles: u32,
+}
 
 /// The Memory Management Unit.
 /// Provides access to all mapped memory in the system, including I/O and graphics.
 pub struct MMU {
     // TODO: MMU should own RAM/High RAM (8k + 256 bytes), maybe some registers.
     data: Vec<u8>,
-    loading_bios: Cell<bool>,
+    // BOOT register (0xFF50), the boot ROM is mapped until bit 0 is set.
+    boot: u8,
+    // DMA register (0xFF46), the last value written.
+    dma_register: u8,
+    dma: Option<DmaTransfer>,
+    boot_rom: BootRom,
     mbc: Box<dyn MemoryBankController>,
+    pub interrupts: InterruptController,
+    pub timer: Timer,
+    pub serial: Serial,
+    pub apu: APU,
+    pub joypad: Joypad,
     pub gpu: GPU,
 }
 
@@ -32,44 +58,179 @@ impl MMU {
     /// Creates a new memory controller with no program loaded, except for the bootrom.
     pub fn new() -> Self {
         MMU {
-            loading_bios: Cell::new(true),
+            boot: 0,
+            dma_register: 0xFF,
+            dma: None,
+            boot_rom: BootRom::dmg(),
             data: vec![0; 65536],
             mbc: Box::new(RomOnly::new()),
+            interrupts: InterruptController::new(),
+            timer: Timer::new(),
+            serial: Serial::new(),
+            apu: APU::new(),
+            joypad: Joypad::new(),
             gpu: GPU::new(),
         }
     }
 
     /// Emulates the behaviour of the system for a certain amount of cycles (`delta`)
     pub fn emulate(&mut self, delta: u32) {
-        self.gpu.emulate(delta);
+        self.emulate_dma(delta);
+        self.timer.emulate(delta, &mut self.interrupts);
+        self.serial.emulate(delta, &mut self.interrupts);
+        self.apu.emulate(delta, self.timer.div());
+        self.gpu.emulate(delta, &mut self.interrupts);
     }
 
     /// Creates a memory controller with the specified cartridge loaded.
-    pub fn new_with_rom(cart: &Cartridge) -> Self {
+    /// The cartridge is accessed through the MBC specified in its header, an error is returned
+    /// if the MBC is not supported.
+    pub fn new_with_rom(cart: &Cartridge) -> Result<Self, String> {
         let mut mmu = MMU::new();
-        mmu.load_rom(cart);
-        mmu
+        mmu.mbc = mbc::from_cartridge(cart)?;
+        Ok(mmu)
+    }
+
+    /// Replaces the boot ROM mapped at startup, the built-in DMG one is used by default.
+    pub fn set_boot_rom(&mut self, boot_rom: BootRom) {
+        self.boot_rom = boot_rom;
+    }
+
+    /// Like `new_with_rom`, but the cartridge header is validated first, the same way the
+    /// boot ROM does it. Cartridges that would lock up on real hardware are rejected.
+    pub fn new_with_validated_rom(cart: &Cartridge) -> Result<Self, String> {
+        cart.validate().map_err(|e| e.to_string())?;
+        MMU::new_with_rom(cart)
+    }
+
+    /// Sets a function to be called when the cartridge rumble motor is turned on or off.
+    pub fn set_rumble_callback(&mut self, callback: RumbleCallback) {
+        self.mbc.set_rumble_callback(callback);
+    }
+
+    /// Unmaps the boot ROM and sets the I/O registers to the state left by the boot ROM of `model`,
+    /// so that the cartridge can be started directly at 0x100.
+    pub fn skip_boot(&mut self, model: Model) {
+        let io = model.post_boot_io();
+        self.boot = 0x01;
+
+        self.timer.set_counter(model.post_boot_counter());
+        // the boot ROM leaves VBlank requested
+        self.interrupts.write_flag(0x01);
+
+        self.write_b(0xFF00, 0x00); // P1, all lines selected
+        self.write_b(0xFF02, io.sc); // SC
+        self.write_b(0xFF07, 0x00); // TAC
+        self.write_b(0xFF26, 0x80); // NR52, APU on
+        self.write_b(0xFF11, 0x80); // NR11
+        // the boot sound leaves channel 1 on, with its envelope faded out to volume 0
+        self.write_b(0xFF12, 0x08);
+        self.write_b(0xFF13, 0xC1); // NR13
+        self.write_b(0xFF14, 0x87); // NR14
+        self.write_b(0xFF12, 0xF3); // NR12
+        self.write_b(0xFF24, 0x77); // NR50
+        self.write_b(0xFF25, 0xF3); // NR51
+        self.write_b(0xFF40, 0x91); // LCDC
+        self.write_b(0xFF42, 0x00); // SCY
+        self.write_b(0xFF43, 0x00); // SCX
+        self.write_b(0xFF45, 0x00); // LYC
+        self.write_b(0xFF47, 0xFC); // BGP
+        // a write would start a transfer
+        self.dma_register = io.dma;
+
+        self.gpu.set_vblank_position(io.lcd_line, io.lcd_cycles);
+    }
+
+    /// Returns the battery backed data of the cartridge, in the layout used by .sav files:
+    /// the content of the external RAM, followed by the RTC state if the MBC has a clock.
+    pub fn battery_data(&mut self) -> Vec<u8> {
+        let mut data = self.mbc.ram().to_vec();
+        if let Some(rtc) = self.mbc.rtc_state() {
+            data.extend_from_slice(&rtc);
+        }
+        data
+    }
+
+    /// Restores the battery backed data of the cartridge from the content of a .sav file.
+    /// The RTC state is only loaded if it's present after the RAM data.
+    pub fn load_battery_data(&mut self, data: &[u8]) -> Result<(), String> {
+        let ram_size = self.mbc.ram().len();
+        self.mbc.load_ram(data);
+
+        if data.len() >= ram_size + RTC_STATE_SIZE {
+            self.mbc.load_rtc_state(&data[ram_size..])?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns true while the boot ROM is mapped over the cartridge ROM.
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot & 0x01 == 0
+    }
+
+    /// Starts an OAM DMA transfer, copying 160 bytes from `high`00 to OAM.
+    /// Sources above 0xDF00 are mapped to work RAM, like the echo area.
+    fn start_dma(&mut self, high: u8) {
+        self.dma_register = high;
+
+        let source = (high as u16) << 8;
+        self.dma = Some(DmaTransfer {
+            source: if source >= 0xE000 { source - 0x2000 } else { source },
+            offset: 0,
+            cycles: 0,
+        });
+    }
+
+    fn emulate_dma(&mut self, delta: u32) {
+        let mut dma = match self.dma.take() {
+            Some(dma) => dma,
+            None => return,
+        };
+
+        dma.cycles += delta;
+
+        // the first 4 cycles are spent setting up the transfer, then a byte is copied every 4 cycles.
+        while dma.offset < 0xA0 && dma.cycles >= 4 * (dma.offset as u32 + 2) {
+            let data = self.read_bus(dma.source + dma.offset);
+            self.gpu.dma_write_oam(dma.offset as usize, data);
+            dma.offset += 1;
+        }
+
+        if dma.offset < 0xA0 {
+            self.dma = Some(dma);
+        }
     }
 
-    fn load_rom(&mut self, cart: &Cartridge) {
-        for i in 0..cart.size {
-            self.data[i] = cart.data[i];
+    /// Returns true if an OAM DMA transfer is copying data.
+    pub fn dma_active(&self) -> bool {
+        match self.dma {
+            Some(ref dma) => dma.cycles >= 4,
+            None => false,
         }
     }
 
+    /// Returns true if the CPU can't access `addr` because an OAM DMA transfer owns the bus.
+    /// Only High RAM is reachable during the transfer.
+    fn dma_blocks(&self, addr: u16) -> bool {
+        self.dma_active() && !(0xFF80..=0xFFFE).contains(&addr)
+    }
+
     /// reads a byte at the memory address specified
+    /// While an OAM DMA transfer is running, everything except High RAM reads 0xFF.
     pub fn read_b(&self, addr: u16) -> u8 {
-        // when PC first reaches 0x100, the BIOS data is not addressable anymore.
-        // using Cell sucks, but this is one of the few cases mentioned by official docs where
-        // it is an option. Read is logically an immutable operation, but we need to make the
-        // bios unaddressable after its first read.
-        if self.loading_bios.get() && addr == 0x0100 {
-            self.loading_bios.set(false);
+        if self.dma_blocks(addr) {
+            return 0xFF;
         }
 
+        self.read_bus(addr)
+    }
+
+    /// reads a byte at the memory address specified, regardless of OAM DMA.
+    fn read_bus(&self, addr: u16) -> u8 {
         match addr {
-            // bios area, 256B long for regular gameboy, only accessible if loading_bios is true.
-            0x0000..=0x00FF if self.loading_bios.get() => DMG_BOOTROM[(addr & 0xFF) as usize],
+            // bios area, 256B long for regular gameboy (plus 0x200-0x8FF on CGB), only accessible until BOOT is set.
+            _ if self.boot_rom_mapped() && self.boot_rom.is_mapped(addr) => self.boot_rom.read(addr),
             // ROM area, this is handled by the MBC
             0x0000..=0x7FFF => self.mbc.read(addr),
             // Graphics, 8kB VRAM
@@ -82,26 +243,43 @@ impl MMU {
             0xE000..=0xFDFF => self.data[(addr - 0x2000) as usize],
             // Sprite attribute memory, 160B
             0xFE00..=0xFE9F => self.gpu.read_oam((addr - 0xFE00) as usize),
+            // BOOT, the unused bits read as 1
+            0xFF50 => 0xFE | self.boot,
             // empty
-            0xFEA0..=0xFEFF | 0xFF4C..=0xFF7F => 0,
+            0xFEA0..=0xFEFF => 0,
+            // unmapped I/O
+            0xFF4C..=0xFF7F => 0xFF,
             // I/O ports
             0xFF00..=0xFF4B => {
                 match addr & 0xFF {
-                    0x40..=0x47 => self.gpu.read_register(addr as usize),
-                    _ => unimplemented!(),
+                    0x00 => self.joypad.read_register(),
+                    0x01..=0x02 => self.serial.read_register(addr as usize),
+                    0x04..=0x07 => self.timer.read_register(addr as usize),
+                    0x0F => self.interrupts.read_flag(),
+                    0x10..=0x3F => self.apu.read_register(addr as usize),
+                    0x46 => self.dma_register,
+                    0x40..=0x4B => self.gpu.read_register(addr as usize),
+                    // unmapped
+                    _ => 0xFF,
                 }
             },
             // High RAM (zero page), used with LDH instructions
             0xFF80..=0xFFFE => self.data[addr as usize],
             // Interrupt Enable register
-            0xFFFF => unimplemented!(),
+            0xFFFF => self.interrupts.read_enable(),
         }
     }
 
+    /// writes a byte at the memory address specified
+    /// While an OAM DMA transfer is running, writes outside of High RAM are ignored.
     pub fn write_b(&mut self, addr: u16, data: u8) {
+        if self.dma_blocks(addr) {
+            return;
+        }
+
         match addr {
             // bios area, 256B long for regular gameboy.
-            0x0000..=0x00FF if self.loading_bios.get() => panic!("Writing to bootrom ${:04x} <- {:02x}", addr, data),
+            0x0000..=0x00FF if self.boot_rom_mapped() => panic!("Writing to bootrom ${:04x} <- {:02x}", addr, data),
             // ROM area, this is banked memory, it will swap according to selected bank
             0x0000..=0x7FFF => self.mbc.write(addr, data),
             // Graphics, 8kB VRAM
@@ -114,19 +292,201 @@ impl MMU {
             0xE000..=0xFDFF => self.data[(addr - 0x2000) as usize] = data,
             // Sprite attribute memory, 160B
             0xFE00..=0xFE9F => self.gpu.write_oam((addr - 0xFE00) as usize, data),
+            // BOOT, the boot ROM writes 1 here at its end, unmapping itself.
+            // Once set, the bit can't be cleared, so the boot ROM can't be mapped again.
+            0xFF50 => self.boot |= data & 0x01,
             // empty
             0xFEA0..=0xFEFF | 0xFF4C..=0xFF7F => (),
             // I/O ports
             0xFF00..=0xFF4B => {
                 match addr & 0xFF {
-                    0x40..=0x47 => self.gpu.write_register(addr as usize, data),
+                    0x00 => self.joypad.write_register(data, &mut self.interrupts),
+                    0x01..=0x02 => self.serial.write_register(addr as usize, data),
+                    0x04..=0x07 => self.timer.write_register(addr as usize, data),
+                    0x0F => self.interrupts.write_flag(data),
+                    0x10..=0x3F => self.apu.write_register(addr as usize, data),
+                    0x46 => self.start_dma(data),
+                    0x40..=0x4B => self.gpu.write_register(addr as usize, data),
                     _ => {},
                 }
             },
             // High RAM (zero page), used with LDH instructions
             0xFF80..=0xFFFE => self.data[addr as usize] = data,
             // Interrupt Enable register
-            0xFFFF => unimplemented!(),
+            0xFFFF => self.interrupts.write_enable(data),
         }
     }
 }
+
+#[test]
+fn battery_data_roundtrip() {
+    // MBC3+TIMER+RAM+BATTERY, 8KB RAM
+    let mut rom = vec![0; 0x8000];
+    rom[0x147] = 0x10;
+    rom[0x149] = 0x02;
+    let cart = Cartridge::new_with_vec(rom).unwrap();
+    let mut mmu = MMU::new_with_rom(&cart).unwrap();
+
+    mmu.write_b(0x1000, 0x0A);
+    mmu.write_b(0xA000, 0x42);
+    mmu.write_b(0xBFFF, 0x24);
+
+    let data = mmu.battery_data();
+    assert_eq!(0x2000 + RTC_STATE_SIZE, data.len());
+
+    let mut loaded = MMU::new_with_rom(&cart).unwrap();
+    loaded.load_battery_data(&data).unwrap();
+    loaded.write_b(0x1000, 0x0A);
+    assert_eq!(0x42, loaded.read_b(0xA000));
+    assert_eq!(0x24, loaded.read_b(0xBFFF));
+}
+
+#[test]
+fn validated_rom() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+    rom[0x14D] = header_checksum(&rom);
+    let cart = Cartridge::new_with_vec(rom.clone()).unwrap();
+    assert!(MMU::new_with_validated_rom(&cart).is_ok());
+
+    // wrong header checksum
+    rom[0x14D] ^= 0xFF;
+    let cart = Cartridge::new_with_vec(rom.clone()).unwrap();
+    match MMU::new_with_validated_rom(&cart) {
+        Err(e) => assert!(e.starts_with("Header checksum mismatch")),
+        Ok(_) => panic!("the header checksum was not checked"),
+    }
+    // the unvalidated path still accepts it
+    assert!(MMU::new_with_rom(&cart).is_ok());
+
+    // wrong logo
+    rom[0x14D] ^= 0xFF;
+    rom[0x110] ^= 0xFF;
+    let cart = Cartridge::new_with_vec(rom).unwrap();
+    match MMU::new_with_validated_rom(&cart) {
+        Err(e) => assert_eq!("Nintendo logo mismatch at $0110", e),
+        Ok(_) => panic!("the logo was not checked"),
+    }
+}
+
+#[test]
+fn boot_rom_unmapped_by_ff50() {
+    let mut rom = vec![0x11; 0x8000];
+    rom[0x147] = 0x00;
+    rom[0x148] = 0x00;
+    rom[0x149] = 0x00;
+    let cart = Cartridge::new_with_vec(rom).unwrap();
+    let mut mmu = MMU::new_with_rom(&cart).unwrap();
+    mmu.set_boot_rom(BootRom::new_with_vec(vec![0x22; 0x900]).unwrap());
+
+    assert_eq!(0x22, mmu.read_b(0x0000));
+    assert_eq!(0x11, mmu.read_b(0x0100));
+    assert_eq!(0x22, mmu.read_b(0x0200));
+    assert_eq!(0x11, mmu.read_b(0x0900));
+
+    // writes without bit 0 set are ignored
+    mmu.write_b(0xFF50, 0x00);
+    assert_eq!(0x22, mmu.read_b(0x0000));
+
+    mmu.write_b(0xFF50, 0x01);
+    assert_eq!(0x11, mmu.read_b(0x0000));
+    assert_eq!(0x11, mmu.read_b(0x0200));
+}
+
+#[test]
+fn boot_register() {
+    let mut mmu = MMU::new();
+    assert!(mmu.boot_rom_mapped());
+    assert_eq!(0xFE, mmu.read_b(0xFF50));
+
+    // reading 0x100 as data doesn't unmap the boot ROM
+    mmu.read_b(0x0100);
+    assert!(mmu.boot_rom_mapped());
+    assert_eq!(0x31, mmu.read_b(0x0000));
+
+    mmu.write_b(0xFF50, 0x01);
+    assert!(!mmu.boot_rom_mapped());
+    assert_eq!(0xFF, mmu.read_b(0xFF50));
+
+    // the boot ROM can't be mapped again
+    mmu.write_b(0xFF50, 0x00);
+    assert!(!mmu.boot_rom_mapped());
+}
+
+#[test]
+fn io_reads() {
+    let mut mmu = MMU::new();
+    mmu.write_b(0xFF50, 0x01);
+
+    for addr in 0xFF00..=0xFF7F {
+        mmu.read_b(addr);
+    }
+
+    // unmapped I/O reads as 0xFF
+    assert_eq!(0xFF, mmu.read_b(0xFF03));
+    assert_eq!(0xFF, mmu.read_b(0xFF08));
+    assert_eq!(0xFF, mmu.read_b(0xFF0E));
+    assert_eq!(0xFF, mmu.read_b(0xFF7F));
+    // SC, the unused bits read as 1
+    assert_eq!(0x7E, mmu.read_b(0xFF02));
+}
+
+#[test]
+fn oam_dma() {
+    let mut mmu = MMU::new();
+    for i in 0..0xA0 {
+        mmu.write_b(0xC000 + i, i as u8 + 1);
+    }
+    mmu.write_b(0xFF80, 0x42);
+
+    mmu.write_b(0xFF46, 0xC0);
+    assert_eq!(0xC0, mmu.read_b(0xFF46));
+
+    // setup cycle, the bus is still free
+    assert!(!mmu.dma_active());
+    mmu.emulate(4);
+    assert!(mmu.dma_active());
+
+    // only High RAM is reachable
+    assert_eq!(0xFF, mmu.read_b(0xC000));
+    assert_eq!(0x42, mmu.read_b(0xFF80));
+    mmu.write_b(0xC000, 0xAA);
+
+    // one byte per M-cycle
+    mmu.emulate(4);
+    assert_eq!(0x01, mmu.gpu.read_oam(0));
+    assert_eq!(0x00, mmu.gpu.read_oam(1));
+
+    mmu.emulate(159 * 4);
+    assert!(!mmu.dma_active());
+    for i in 0..0xA0 {
+        assert_eq!(i as u8 + 1, mmu.gpu.read_oam(i));
+    }
+    // the write during the transfer was ignored
+    assert_eq!(0x01, mmu.read_b(0xC000));
+}
+
+#[test]
+fn oam_dma_while_oam_locked() {
+    let mut mmu = MMU::new();
+    for i in 0..0xA0 {
+        mmu.write_b(0xC000 + i, i as u8);
+    }
+    mmu.write_b(0xFF40, 0x80);
+
+    // OAM is locked for the CPU in mode 2, but not for the DMA
+    mmu.write_b(0xFF46, 0xC0);
+    mmu.emulate(12);
+    mmu.gpu.set_access_locks(false);
+    assert_eq!(0x01, mmu.gpu.read_oam(1));
+    mmu.gpu.set_access_locks(true);
+
+    // the CPU side goes through the locks, line 1 is in mode 3 when the transfer ends
+    mmu.emulate(158 * 4);
+    assert_eq!(0xFF, mmu.read_b(0xFE01));
+    assert_eq!(0xFF, mmu.read_b(0x8000));
+
+    // in HBlank the copied data is visible
+    mmu.emulate(100);
+    assert_eq!(0x9F, mmu.read_b(0xFE9F));
+}
@@ -5,6 +5,11 @@
 pub mod core;
 pub mod video;
 pub mod memory;
+pub mod interrupt;
+pub mod timer;
+pub mod serial;
+pub mod apu;
+pub mod joypad;
 pub mod instr;
 pub mod mbc;
 pub mod cart;
@@ -0,0 +1,113 @@
+//! The serial port, mapped to the SB (0xFF01) and SC (0xFF02) registers.
+//!
+//! A transfer shifts the 8 bits of SB out while shifting in the bits sent by the other side.
+//! With the internal clock a bit is shifted every 512 cycles (8192 Hz), with the external clock
+//! the other gameboy drives the transfer. No link cable is emulated, so only transfers using the
+//! internal clock complete, receiving 0xFF as if nothing was connected.
+use jeebie::interrupt::{Interrupt, InterruptController};
+
+/// Clock cycles needed to shift one bit with the internal clock.
+const BIT_CYCLES: u32 = 512;
+
+pub struct Serial {
+    sb: u8,
+    // SC register, bit 7 starts a transfer and bit 0 selects the internal clock
+    sc: u8,
+    // bits left to shift in the current transfer, and cycles spent on the current bit
+    bits: u8,
+    cycles: u32,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            sb: 0,
+            sc: 0,
+            bits: 0,
+            cycles: 0,
+        }
+    }
+
+    /// Emulates the serial port for `delta` clock cycles.
+    /// A serial interrupt is requested through `interrupts` when a transfer completes.
+    pub fn emulate(&mut self, delta: u32, interrupts: &mut InterruptController) {
+        // transfers using the external clock wait forever
+        if self.bits == 0 || self.sc & 0x01 == 0 {
+            return;
+        }
+
+        self.cycles += delta;
+        while self.bits > 0 && self.cycles >= BIT_CYCLES {
+            self.cycles -= BIT_CYCLES;
+            self.bits -= 1;
+            // the disconnected line is pulled high
+            self.sb = (self.sb << 1) | 0x01;
+
+            if self.bits == 0 {
+                self.sc &= 0x7F;
+                interrupts.request(Interrupt::Serial);
+            }
+        }
+    }
+
+    pub fn read_register(&self, addr: usize) -> u8 {
+        match addr {
+            0xFF01 => self.sb,
+            // unused bits read as 1
+            0xFF02 => self.sc | 0x7E,
+            _ => panic!("Attempted serial register access with addr {:4x}", addr),
+        }
+    }
+
+    pub fn write_register(&mut self, addr: usize, data: u8) {
+        match addr {
+            0xFF01 => self.sb = data,
+            0xFF02 => {
+                self.sc = data & 0x81;
+
+                if self.sc & 0x80 != 0 {
+                    self.bits = 8;
+                    self.cycles = 0;
+                } else {
+                    self.bits = 0;
+                }
+            },
+            _ => panic!("Attempted serial register write with addr {:4x}", addr),
+        }
+    }
+}
+
+#[test]
+fn internal_clock_transfer_test() {
+    let mut serial = Serial::new();
+    let mut ic = InterruptController::new();
+    ic.write_enable(0xFF);
+
+    serial.write_register(0xFF01, 0x42);
+    serial.write_register(0xFF02, 0x81);
+    assert_eq!(0xFF, serial.read_register(0xFF02));
+
+    serial.emulate(8 * BIT_CYCLES - 1, &mut ic);
+    assert_eq!(0xFF, serial.read_register(0xFF02));
+    assert_eq!(0, ic.pending());
+
+    // nothing is connected, 0xFF is received
+    serial.emulate(1, &mut ic);
+    assert_eq!(0xFF, serial.read_register(0xFF01));
+    assert_eq!(0x7F, serial.read_register(0xFF02));
+    assert_eq!(Interrupt::Serial as u8, ic.pending());
+}
+
+#[test]
+fn external_clock_transfer_test() {
+    let mut serial = Serial::new();
+    let mut ic = InterruptController::new();
+    ic.write_enable(0xFF);
+
+    serial.write_register(0xFF01, 0x42);
+    serial.write_register(0xFF02, 0x80);
+    serial.emulate(16 * BIT_CYCLES, &mut ic);
+    assert_eq!(0x42, serial.read_register(0xFF01));
+    assert_eq!(0xFE, serial.read_register(0xFF02));
+    assert_eq!(0, ic.pending());
+}
@@ -0,0 +1,204 @@
+//! The timer subsystem, mapped to the DIV, TIMA, TMA and TAC registers (0xFF04 to 0xFF07).
+//!
+//! The timer is driven by a 16-bit internal counter incremented every clock cycle, DIV exposes
+//! its upper 8 bits. TIMA is incremented when the counter bit selected by TAC goes from 1 to 0
+//! (a falling edge), which means that writes to DIV or TAC can also increment TIMA.
+use jeebie::interrupt::{Interrupt, InterruptController};
+
+/// Clock cycles between a TIMA overflow and its reload with TMA.
+const RELOAD_DELAY: u8 = 4;
+
+pub struct Timer {
+    // internal counter, DIV is the upper byte
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // cycles left before TIMA is reloaded after an overflow, 0 if no reload is pending
+    reload_cycles: u8,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            reload_cycles: 0,
+        }
+    }
+
+    /// Emulates the timer for `delta` clock cycles.
+    /// A timer interrupt is requested through `interrupts` when TIMA is reloaded.
+    pub fn emulate(&mut self, delta: u32, interrupts: &mut InterruptController) {
+        for _ in 0..delta {
+            if self.reload_cycles > 0 {
+                self.reload_cycles -= 1;
+
+                if self.reload_cycles == 0 {
+                    self.tima = self.tma;
+                    interrupts.request(Interrupt::Timer);
+                }
+            }
+
+            let old_signal = self.signal();
+            self.counter = self.counter.wrapping_add(1);
+            self.check_falling_edge(old_signal);
+        }
+    }
+
+    /// Sets the internal counter, used to start from the state left by the boot ROM.
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
+
+    /// Returns the value of DIV, the upper byte of the internal counter.
+    pub fn div(&self) -> u8 {
+        (self.counter >> 8) as u8
+    }
+
+    pub fn read_register(&self, addr: usize) -> u8 {
+        match addr {
+            0xFF04 => self.div(),
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            // unused bits read as 1
+            0xFF07 => self.tac | 0xF8,
+            _ => panic!("Attempted timer register access with addr {:4x}", addr),
+        }
+    }
+
+    pub fn write_register(&mut self, addr: usize, data: u8) {
+        let old_signal = self.signal();
+
+        match addr {
+            // any write resets the whole internal counter
+            0xFF04 => self.counter = 0,
+            0xFF05 => {
+                // writing during the reload delay cancels the reload
+                self.reload_cycles = 0;
+                self.tima = data;
+            },
+            0xFF06 => self.tma = data,
+            0xFF07 => self.tac = data & 0x07,
+            _ => panic!("Attempted timer register write with addr {:4x}", addr),
+        };
+
+        self.check_falling_edge(old_signal);
+    }
+
+    /// Returns the counter bit selected by TAC, ANDed with the timer enable bit.
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0 => 9, // 4096 Hz
+            1 => 3, // 262144 Hz
+            2 => 5, // 65536 Hz
+            _ => 7, // 16384 Hz
+        };
+
+        (self.tac & 0x04) != 0 && (self.counter >> bit) & 1 == 1
+    }
+
+    /// Increments TIMA if the timer signal went from high to low.
+    fn check_falling_edge(&mut self, old_signal: bool) {
+        if !old_signal || self.signal() {
+            return;
+        }
+
+        let (result, overflow) = self.tima.overflowing_add(1);
+        self.tima = result;
+
+        // TIMA stays at 0 for a few cycles before being reloaded
+        if overflow {
+            self.reload_cycles = RELOAD_DELAY;
+        }
+    }
+}
+
+#[test]
+fn div_test() {
+    let mut timer = Timer::new();
+    let mut ic = InterruptController::new();
+
+    timer.emulate(255, &mut ic);
+    assert_eq!(0, timer.read_register(0xFF04));
+    timer.emulate(1, &mut ic);
+    assert_eq!(1, timer.read_register(0xFF04));
+
+    // writing any value resets it
+    timer.write_register(0xFF04, 0xAB);
+    assert_eq!(0, timer.read_register(0xFF04));
+}
+
+#[test]
+fn tima_increment_test() {
+    let mut timer = Timer::new();
+    let mut ic = InterruptController::new();
+
+    // enabled, 262144 Hz (every 16 cycles)
+    timer.write_register(0xFF07, 0x05);
+    timer.emulate(15, &mut ic);
+    assert_eq!(0, timer.read_register(0xFF05));
+    timer.emulate(1, &mut ic);
+    assert_eq!(1, timer.read_register(0xFF05));
+    timer.emulate(32, &mut ic);
+    assert_eq!(3, timer.read_register(0xFF05));
+
+    // disabled, TIMA is not incremented
+    timer.write_register(0xFF07, 0x01);
+    timer.emulate(64, &mut ic);
+    assert_eq!(3, timer.read_register(0xFF05));
+}
+
+#[test]
+fn tima_overflow_test() {
+    let mut timer = Timer::new();
+    let mut ic = InterruptController::new();
+    ic.write_enable(0xFF);
+
+    timer.write_register(0xFF06, 0xAA);
+    timer.write_register(0xFF05, 0xFF);
+    timer.write_register(0xFF07, 0x05);
+
+    // TIMA overflows and reads 0 until the reload
+    timer.emulate(16, &mut ic);
+    assert_eq!(0, timer.read_register(0xFF05));
+    assert_eq!(0, ic.pending());
+
+    timer.emulate(4, &mut ic);
+    assert_eq!(0xAA, timer.read_register(0xFF05));
+    assert_eq!(Interrupt::Timer as u8, ic.pending());
+}
+
+#[test]
+fn tima_write_cancels_reload_test() {
+    let mut timer = Timer::new();
+    let mut ic = InterruptController::new();
+    ic.write_enable(0xFF);
+
+    timer.write_register(0xFF06, 0xAA);
+    timer.write_register(0xFF05, 0xFF);
+    timer.write_register(0xFF07, 0x05);
+
+    timer.emulate(16, &mut ic);
+    timer.write_register(0xFF05, 0x10);
+    timer.emulate(4, &mut ic);
+    assert_eq!(0x10, timer.read_register(0xFF05));
+    assert_eq!(0, ic.pending());
+}
+
+#[test]
+fn div_write_falling_edge_test() {
+    let mut timer = Timer::new();
+    let mut ic = InterruptController::new();
+
+    // enabled, 262144 Hz (bit 3)
+    timer.write_register(0xFF07, 0x05);
+    timer.emulate(8, &mut ic);
+    assert_eq!(0, timer.read_register(0xFF05));
+
+    // bit 3 is set, resetting the counter causes a falling edge
+    timer.write_register(0xFF04, 0);
+    assert_eq!(1, timer.read_register(0xFF05));
+}
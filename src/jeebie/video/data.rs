@@ -49,6 +49,7 @@ impl VideoMemory {
 ///  Mode 3 (`VRAMRead`): The LCD controller is reading from both OAM and VRAM,
 ///          The CPU <cannot> access OAM and VRAM during this period.
 ///          CGB Mode: Cannot access Palette Data (`FF69h`,`FF6Bh`) either.
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Mode {
     HBlank,
     VBlank,
@@ -91,6 +92,25 @@ impl GBColor {
     }
 }
 
+/// A DMG palette register (BGP, OBP0 or OBP1), mapping the 4 colour indices of a pixel to shades.
+/// Bits 1-0 hold the shade for index 0, bits 3-2 for index 1, and so on.
+/// For sprites the shade for index 0 is unused, as index 0 is always transparent.
+#[derive(Copy, Clone)]
+pub struct Palette {
+    pub value: u8,
+}
+
+impl Palette {
+    pub fn new(value: u8) -> Palette {
+        Palette { value: value }
+    }
+
+    /// Returns the shade of colour index `index` (0 to 3).
+    pub fn apply(&self, index: u8) -> GBColor {
+        GBColor::from_u8((self.value >> (index * 2)) & 0x03)
+    }
+}
+
 /// An enum used to discriminate tilesets (and maps)
 #[derive(Copy, Clone)]
 pub enum TileSelector {
@@ -200,20 +220,12 @@ impl LCDStatus {
         }
     }
 
+    /// Sets the interrupt enable bits, the coincidence flag and the mode are read only.
     pub fn set_from_u8(&mut self, data: u8) {
         self.coincidence_irq = is_set(data, 6);
         self.oam_irq = is_set(data, 5);
         self.vblank_irq = is_set(data, 4);
         self.hblank_irq = is_set(data, 3);
-        self.coincidence_flag = is_set(data, 2);
-
-        self.mode = match data & 0x3 {
-            0 => Mode::HBlank,
-            1 => Mode::VBlank,
-            2 => Mode::OAMRead,
-            3 => Mode::VRAMRead,
-            _ => panic!("invalid mode flag {:?}", data & 0x3)
-        };
     }
 
     pub fn to_u8(&self) -> u8 {
@@ -236,14 +248,44 @@ impl LCDStatus {
     }
 }
 
-/// A tile is an 8x8 square of pixels, each one stored in one of the VRAM's tilesets.
-pub struct Tile {
-    pub pixels: [GBColor; 64],
+/// A sprite (object) as stored in OAM, 4 bytes each:
+///   Byte0  Y position on the screen, plus 16
+///   Byte1  X position on the screen, plus 8
+///   Byte2  Tile index, sprites always use the tiles at 8000-8FFF
+///   Byte3  Flags:
+///     Bit7  Priority, if set the sprite is hidden behind BG and window colours 1-3
+///     Bit6  Y flip
+///     Bit5  X flip
+///     Bit4  Palette number (0=OBP0, 1=OBP1)
+#[derive(Copy, Clone, Debug)]
+pub struct Sprite {
+    pub y: i32,
+    pub x: i32,
+    pub tile: u8,
+    pub behind_bg: bool,
+    pub y_flip: bool,
+    pub x_flip: bool,
+    pub use_obp1: bool,
+    // position in OAM, used to break ties in priority.
+    pub index: usize,
 }
 
-impl Tile {
-    pub fn new() -> Tile {
-        Tile { pixels: [GBColor::Off; 64] }
+impl Sprite {
+    /// Reads the sprite number `index` from OAM.
+    pub fn from_oam(oam: &[u8], index: usize) -> Sprite {
+        let data = &oam[index * 4..index * 4 + 4];
+        let flags = data[3];
+
+        Sprite {
+            y: data[0] as i32 - 16,
+            x: data[1] as i32 - 8,
+            tile: data[2],
+            behind_bg: is_set(flags, 7),
+            y_flip: is_set(flags, 6),
+            x_flip: is_set(flags, 5),
+            use_obp1: is_set(flags, 4),
+            index: index,
+        }
     }
 }
 
@@ -0,0 +1,478 @@
+use std::collections::VecDeque;
+
+use super::data::*;
+use super::gpu::{GPU, SCREEN_WIDTH};
+use super::renderer::Renderer;
+
+use jeebie::utils::is_set;
+
+#[cfg(test)]
+use jeebie::interrupt::InterruptController;
+
+// cycles spent on the first tile fetch of a line, whose pixels are thrown away.
+const INITIAL_FETCH_CYCLES: u32 = 6;
+// cycles spent fetching a sprite once the background fetcher has a tile ready.
+const SPRITE_FETCH_CYCLES: u32 = 6;
+
+/// The steps of the background fetcher, all of them but Push take 2 cycles.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum FetcherStep {
+    TileIndex,
+    DataLow,
+    DataHigh,
+    // waits for the background FIFO to be empty, then pushes the 8 pixels of the tile.
+    Push,
+}
+
+/// A sprite pixel waiting in the sprite FIFO, with the attributes needed to mix it with the background.
+#[derive(Clone, Copy)]
+struct SpritePixel {
+    color: u8,
+    use_obp1: bool,
+    behind_bg: bool,
+}
+
+/// Renders the lines pixel by pixel, the way the PPU does during mode 3.
+///
+/// A fetcher reads the background (or window) tiles from VRAM and pushes them 8 pixels at a time
+/// in the background FIFO, which shifts out one pixel per cycle to the LCD. Sprites are fetched
+/// when the LCD reaches their X and mixed in through a second FIFO.
+/// Registers are read while the line is drawn, so writes during mode 3 affect the rest of the line.
+///
+/// Mode 3 lasts at least 172 cycles, and takes longer when:
+///  - SCX % 8 pixels are discarded at the start of the line
+///  - the window starts, restarting the fetcher (6 cycles)
+///  - a sprite is fetched, 6 cycles plus the time needed by the fetcher to finish the current tile
+pub struct PixelFifo {
+    bg_fifo: VecDeque<u8>,
+    sprite_fifo: VecDeque<SpritePixel>,
+    step: FetcherStep,
+    step_cycles: u32,
+    // tile column being fetched, relative to SCX for the background or to the window start.
+    fetcher_x: u8,
+    tile_index: u8,
+    tile_low: u8,
+    tile_high: u8,
+    fetching_window: bool,
+    // x of the next pixel sent to the LCD.
+    lcd_x: u8,
+    // pixels to drop before the next one is sent to the LCD, for SCX % 8 or WX < 7.
+    discard: u8,
+    // cycles to wait before the fetcher starts.
+    delay: u32,
+    // sprites found by the OAM search and not fetched yet.
+    sprites: Vec<Sprite>,
+    // the sprite being fetched, and the cycles spent on it.
+    sprite: Option<Sprite>,
+    sprite_cycles: u32,
+}
+
+impl PixelFifo {
+    pub fn new() -> PixelFifo {
+        PixelFifo {
+            bg_fifo: VecDeque::new(),
+            sprite_fifo: VecDeque::new(),
+            step: FetcherStep::TileIndex,
+            step_cycles: 0,
+            fetcher_x: 0,
+            tile_index: 0,
+            tile_low: 0,
+            tile_high: 0,
+            fetching_window: false,
+            lcd_x: 0,
+            discard: 0,
+            delay: 0,
+            sprites: vec![],
+            sprite: None,
+            sprite_cycles: 0,
+        }
+    }
+
+    /// Advances the background fetcher by one cycle.
+    fn tick_fetcher(&mut self, gpu: &GPU) {
+        if self.step == FetcherStep::Push {
+            if self.bg_fifo.is_empty() {
+                for x in 0..8 {
+                    // bit 7 is the leftmost pixel
+                    let bit = 7 - x;
+                    let pixel = if is_set(self.tile_low, bit) { 1 } else { 0 } |
+                                if is_set(self.tile_high, bit) { 2 } else { 0 };
+                    self.bg_fifo.push_back(pixel);
+                }
+
+                self.fetcher_x = self.fetcher_x.wrapping_add(1);
+                self.step = FetcherStep::TileIndex;
+            }
+            return;
+        }
+
+        self.step_cycles += 1;
+        if self.step_cycles < 2 {
+            return;
+        }
+        self.step_cycles = 0;
+
+        self.step = match self.step {
+            FetcherStep::TileIndex => {
+                self.tile_index = gpu.vram.data[self.tile_map_address(gpu)];
+                FetcherStep::DataLow
+            }
+            FetcherStep::DataLow => {
+                self.tile_low = gpu.vram.data[self.tile_data_address(gpu)];
+                FetcherStep::DataHigh
+            }
+            FetcherStep::DataHigh => {
+                self.tile_high = gpu.vram.data[self.tile_data_address(gpu) + 1];
+                FetcherStep::Push
+            }
+            FetcherStep::Push => unreachable!(),
+        };
+    }
+
+    /// Returns the VRAM address of the tile index to fetch, in the background or window tile map.
+    fn tile_map_address(&self, gpu: &GPU) -> usize {
+        let (tile_map, x, y) = if self.fetching_window {
+            (gpu.lcdc.window_tile_map, self.fetcher_x, gpu.window_line)
+        } else {
+            let x = (gpu.lcdp.scroll_x / 8).wrapping_add(self.fetcher_x) & 0x1F;
+            (gpu.lcdc.bg_tile_map, x, gpu.line.wrapping_add(gpu.lcdp.scroll_y))
+        };
+
+        let tilemap_start = match tile_map {
+            TileSelector::Set0 => 0x1800,
+            TileSelector::Set1 => 0x1C00,
+        };
+
+        tilemap_start + (y as usize / 8) * 32 + (x as usize & 0x1F)
+    }
+
+    /// Returns the VRAM address of the low byte of the current row of the fetched tile.
+    fn tile_data_address(&self, gpu: &GPU) -> usize {
+        let y = if self.fetching_window {
+            gpu.window_line
+        } else {
+            gpu.line.wrapping_add(gpu.lcdp.scroll_y)
+        };
+
+        gpu.tile_data_address(self.tile_index) + (y as usize % 8) * 2
+    }
+
+    /// Switches the fetcher to the window when the LCD reaches WX-7.
+    /// With WX<7 the window starts at the first pixel, and its leftmost columns are cut off.
+    fn start_window(&mut self, gpu: &GPU) {
+        // on DMG, LCDC bit 0 hides the window too
+        let wx = gpu.lcdp.window_x;
+        if self.fetching_window || !gpu.lcdc.window_enable || !gpu.lcdc.bg_enable ||
+           !gpu.window_triggered || (self.lcd_x as u16) + 7 < wx as u16 {
+            return;
+        }
+
+        self.fetching_window = true;
+        self.bg_fifo.clear();
+        self.step = FetcherStep::TileIndex;
+        self.step_cycles = 0;
+        self.fetcher_x = 0;
+        self.discard = 7u8.saturating_sub(wx);
+    }
+
+    /// Fetches the current row of a sprite and merges it in the sprite FIFO.
+    /// Pixels already in the FIFO come from sprites with higher priority (smaller X, or earlier in OAM
+    /// with the same X), so they're only replaced where they're transparent.
+    fn fetch_sprite(&mut self, gpu: &GPU, sprite: Sprite) {
+        let height = gpu.lcdc.sprite_size as i32;
+        let row = if sprite.y_flip {
+            height - 1 - (gpu.line as i32 - sprite.y)
+        } else {
+            gpu.line as i32 - sprite.y
+        };
+
+        // in 8x16 mode, the top tile is the even one and the bottom tile is the next one
+        let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile } as usize;
+        let tile_address = tile * 16 + row as usize * 2;
+        let low_byte = gpu.vram.data[tile_address];
+        let high_byte = gpu.vram.data[tile_address + 1];
+
+        // sprites partially off the left edge are fetched at the first pixel, minus the hidden columns
+        let hidden = (self.lcd_x as i32 - sprite.x) as usize;
+
+        for x in hidden..8 {
+            // bit 7 is the leftmost pixel
+            let bit = if sprite.x_flip { x } else { 7 - x };
+            let pixel = SpritePixel {
+                color: if is_set(low_byte, bit) { 1 } else { 0 } |
+                       if is_set(high_byte, bit) { 2 } else { 0 },
+                use_obp1: sprite.use_obp1,
+                behind_bg: sprite.behind_bg,
+            };
+
+            let slot = x - hidden;
+            if slot >= self.sprite_fifo.len() {
+                self.sprite_fifo.push_back(pixel);
+            } else if self.sprite_fifo[slot].color == 0 {
+                self.sprite_fifo[slot] = pixel;
+            }
+        }
+    }
+
+    /// Shifts a pixel out of the FIFOs to the LCD, unless it has to be discarded or a sprite
+    /// has to be fetched first.
+    fn shift_pixel(&mut self, gpu: &mut GPU) {
+        if self.bg_fifo.is_empty() {
+            return;
+        }
+
+        if self.discard > 0 {
+            self.bg_fifo.pop_front();
+            self.discard -= 1;
+            return;
+        }
+
+        if gpu.lcdc.sprite_enable {
+            let lcd_x = self.lcd_x as i32;
+            if let Some(i) = self.sprites.iter().position(|sprite| sprite.x.max(0) == lcd_x) {
+                self.sprite = Some(self.sprites.remove(i));
+                return;
+            }
+        }
+
+        let x = self.lcd_x as usize;
+        // on DMG, clearing LCDC bit 0 makes the background (and window) white
+        let pixel = self.bg_fifo.pop_front().unwrap();
+        let bg = if gpu.lcdc.bg_enable { pixel } else { 0 };
+
+        let color = match self.sprite_fifo.pop_front() {
+            // colour 0 is transparent, and the background hides the sprite if it has priority
+            Some(pixel) if gpu.lcdc.sprite_enable && pixel.color != 0 && !(pixel.behind_bg && bg != 0) => {
+                let palette = if pixel.use_obp1 { gpu.obp1 } else { gpu.obp0 };
+                palette.apply(pixel.color)
+            }
+            _ if gpu.lcdc.bg_enable => gpu.bgp.apply(bg),
+            _ => GBColor::Off,
+        };
+
+        gpu.bg_line[x] = bg;
+        gpu.framebuffer[gpu.line as usize * SCREEN_WIDTH as usize + x] = color.to_u8u8u8();
+        self.lcd_x += 1;
+    }
+}
+
+impl Renderer for PixelFifo {
+    fn start_line(&mut self, gpu: &mut GPU) {
+        gpu.update_window_trigger();
+
+        *self = PixelFifo {
+            // sprites with X=0 are completely off screen
+            sprites: gpu.scan_oam().into_iter().filter(|sprite| sprite.x > -8).collect(),
+            discard: gpu.lcdp.scroll_x % 8,
+            delay: INITIAL_FETCH_CYCLES,
+            ..PixelFifo::new()
+        };
+    }
+
+    fn tick(&mut self, gpu: &mut GPU) -> bool {
+        if self.delay > 0 {
+            self.delay -= 1;
+            return false;
+        }
+
+        if let Some(sprite) = self.sprite {
+            // the sprite fetch waits for the background fetcher to have a tile ready
+            if self.step != FetcherStep::Push {
+                self.tick_fetcher(gpu);
+            }
+            if self.step == FetcherStep::Push {
+                self.sprite_cycles += 1;
+            }
+            if self.sprite_cycles < SPRITE_FETCH_CYCLES {
+                return false;
+            }
+
+            self.fetch_sprite(gpu, sprite);
+            self.sprite = None;
+            self.sprite_cycles = 0;
+        } else {
+            self.start_window(gpu);
+            self.tick_fetcher(gpu);
+        }
+
+        self.shift_pixel(gpu);
+
+        if self.lcd_x < SCREEN_WIDTH as u8 {
+            return false;
+        }
+
+        if self.fetching_window {
+            gpu.window_line += 1;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+fn fifo_gpu() -> GPU {
+    let mut gpu = GPU::new();
+    gpu.set_renderer(Box::new(PixelFifo::new()));
+    gpu
+}
+
+/// Counts the cycles spent in mode 3 on the first line after turning the LCD on with `lcdc`.
+#[cfg(test)]
+fn mode_3_length(gpu: &mut GPU, lcdc: u8) -> u32 {
+    let mut interrupts = InterruptController::new();
+    gpu.write_register(0xFF40, lcdc);
+
+    let mut cycles = 0;
+    for _ in 0..456 {
+        gpu.emulate(1, &mut interrupts);
+        if gpu.read_register(0xFF41) & 0x03 == 3 {
+            cycles += 1;
+        }
+    }
+    cycles
+}
+
+/// Fills VRAM with tiles, maps and sprites using all the features of the PPU.
+#[cfg(test)]
+fn write_scene(gpu: &mut GPU) {
+    for addr in 0..0x1800 {
+        gpu.write_vram(addr, ((addr * 37 + 11) % 251) as u8);
+    }
+    for addr in 0x1800..0x2000 {
+        gpu.write_vram(addr, (addr * 7) as u8);
+    }
+
+    // (y, x, tile, flags), with overlapping sprites, flips, BG priority and a sprite off the left edge
+    let sprites = [
+        (20, 30, 1, 0x00), (22, 34, 2, 0x20), (20, 30, 3, 0x10), (40, 3, 4, 0x40),
+        (60, 80, 5, 0x80), (62, 84, 6, 0xF0), (100, 160, 7, 0x00), (130, 100, 8, 0x30),
+    ];
+    for (i, &(y, x, tile, flags)) in sprites.iter().enumerate() {
+        gpu.write_oam(i * 4, y);
+        gpu.write_oam(i * 4 + 1, x);
+        gpu.write_oam(i * 4 + 2, tile);
+        gpu.write_oam(i * 4 + 3, flags);
+    }
+
+    gpu.write_register(0xFF42, 5);
+    gpu.write_register(0xFF43, 13);
+    gpu.write_register(0xFF47, 0xE4);
+    gpu.write_register(0xFF48, 0xD2);
+    gpu.write_register(0xFF49, 0x1B);
+    gpu.write_register(0xFF4A, 40);
+    gpu.write_register(0xFF4B, 87);
+}
+
+#[test]
+fn fifo_matches_scanline() {
+    let mut interrupts = InterruptController::new();
+
+    let mut scanline = GPU::new();
+    write_scene(&mut scanline);
+    scanline.write_register(0xFF40, 0xF3);
+    scanline.emulate(70224, &mut interrupts);
+
+    let mut fifo = fifo_gpu();
+    write_scene(&mut fifo);
+    fifo.write_register(0xFF40, 0xF3);
+    fifo.emulate(70224, &mut interrupts);
+
+    assert!(scanline.get_framebuffer() == fifo.get_framebuffer());
+
+    // 8800 addressing, 8x16 sprites and the other tile maps
+    scanline.write_register(0xFF40, 0x00);
+    scanline.write_register(0xFF40, 0x8F);
+    scanline.emulate(70224, &mut interrupts);
+
+    fifo.write_register(0xFF40, 0x00);
+    fifo.write_register(0xFF40, 0x8F);
+    fifo.emulate(70224, &mut interrupts);
+
+    assert!(scanline.get_framebuffer() == fifo.get_framebuffer());
+}
+
+#[test]
+fn fifo_mode_3_length() {
+    let mut gpu = fifo_gpu();
+    assert_eq!(172, mode_3_length(&mut gpu, 0x91));
+
+    // SCX % 8 pixels are discarded
+    let mut gpu = fifo_gpu();
+    gpu.write_register(0xFF43, 0x0B);
+    assert_eq!(175, mode_3_length(&mut gpu, 0x91));
+
+    // the window restarts the fetcher
+    let mut gpu = fifo_gpu();
+    gpu.write_register(0xFF4A, 0);
+    gpu.write_register(0xFF4B, 87);
+    assert_eq!(178, mode_3_length(&mut gpu, 0xB1));
+
+    // a sprite at the start of a tile waits for the whole tile fetch
+    let mut gpu = fifo_gpu();
+    gpu.write_oam(0, 16);
+    gpu.write_oam(1, 8);
+    assert_eq!(183, mode_3_length(&mut gpu, 0x93));
+
+    // a sprite in the middle of a tile waits less, and sprites at the same X only take 6 cycles
+    let mut gpu = fifo_gpu();
+    gpu.write_oam(0, 16);
+    gpu.write_oam(1, 12);
+    gpu.write_oam(4, 16);
+    gpu.write_oam(5, 12);
+    assert_eq!(172 + 7 + 6, mode_3_length(&mut gpu, 0x93));
+
+    // disabled sprites are not fetched
+    let mut gpu = fifo_gpu();
+    gpu.write_oam(0, 16);
+    gpu.write_oam(1, 8);
+    assert_eq!(172, mode_3_length(&mut gpu, 0x91));
+}
+
+#[test]
+fn fifo_mid_line_palette_write() {
+    let mut interrupts = InterruptController::new();
+    let mut gpu = fifo_gpu();
+
+    // tile 0 is all colour 3, and fills the background
+    for addr in 0..16 {
+        gpu.write_vram(addr, 0xFF);
+    }
+    gpu.write_register(0xFF47, 0xFF);
+    gpu.write_register(0xFF40, 0x91);
+
+    // pixel x is sent to the LCD on cycle 93 + x of the line
+    gpu.emulate(93 + 80, &mut interrupts);
+    gpu.write_register(0xFF47, 0x00);
+    gpu.emulate(456 - 93 - 80, &mut interrupts);
+
+    let fb = gpu.get_framebuffer();
+    assert_eq!(GBColor::On.to_u8u8u8(), fb[0]);
+    assert_eq!(GBColor::On.to_u8u8u8(), fb[80]);
+    assert_eq!(GBColor::Off.to_u8u8u8(), fb[81]);
+    assert_eq!(GBColor::Off.to_u8u8u8(), fb[159]);
+}
+
+#[test]
+fn fifo_mid_line_scroll_write() {
+    let mut interrupts = InterruptController::new();
+    let mut gpu = fifo_gpu();
+
+    // tile 1 is all colour 3, and fills the second row of the map
+    for addr in 16..32 {
+        gpu.write_vram(addr, 0xFF);
+    }
+    for addr in 0x1820..0x1840 {
+        gpu.write_vram(addr, 1);
+    }
+    gpu.write_register(0xFF40, 0x91);
+
+    // the scroll is read when each tile is fetched
+    gpu.emulate(93 + 40, &mut interrupts);
+    gpu.write_register(0xFF42, 8);
+    gpu.emulate(456 - 93 - 40, &mut interrupts);
+
+    let fb = gpu.get_framebuffer();
+    assert_eq!(GBColor::Off.to_u8u8u8(), fb[0]);
+    assert_eq!(GBColor::Off.to_u8u8u8(), fb[47]);
+    assert_eq!(GBColor::On.to_u8u8u8(), fb[48]);
+    assert_eq!(GBColor::On.to_u8u8u8(), fb[159]);
+}
@@ -1,22 +1,51 @@
 use super::data::*;
+use super::renderer::{Renderer, ScanlineRenderer};
 
 use jeebie::utils::is_set;
+use jeebie::interrupt::{Interrupt, InterruptController};
 
 
-const SCREEN_WIDTH: i32 = 160;
+pub(super) const SCREEN_WIDTH: i32 = 160;
 const SCREEN_HEIGHT: i32 = 144;
 
+// cycles spent in each mode of a visible scanline, HBlank takes the rest of the line.
+// the pixel transfer can take longer depending on the renderer.
+const OAM_SEARCH_CYCLES: u32 = 80;
+pub(super) const PIXEL_TRANSFER_CYCLES: u32 = 172;
+const LINE_CYCLES: u32 = 456;
+// the last VBlank line, after this the next frame starts from line 0.
+const LAST_LINE: u8 = 153;
+// cycles into the last line after which LY already reads 0.
+const LAST_LINE_LY_CYCLES: u32 = 4;
+// the PPU can only fetch this many sprites in a scanline.
+const MAX_SPRITES_PER_LINE: usize = 10;
+
 /// Holds all information relative to the graphics subsystem.
 /// Includes computed data like the framebuffer, in a format that can be drawn to screen.
 pub struct GPU {
-    line: u8,
+    pub(super) line: u8,
     lyc: u8, // LYC (line Y compare) register
+    // cycles elapsed in the current scanline.
     cycles: u32,
-    vram: VideoMemory,
-    lcdc: LCDControl,
-    lcdp: LCDPosition,
+    // the STAT interrupt line, interrupts are requested on its rising edge.
+    stat_line: bool,
+    pub(super) vram: VideoMemory,
+    pub(super) lcdc: LCDControl,
+    pub(super) lcdp: LCDPosition,
     lcds: LCDStatus,
-    framebuffer: [(u8, u8, u8); (SCREEN_WIDTH * SCREEN_HEIGHT) as usize]
+    pub(super) bgp: Palette,
+    pub(super) obp0: Palette,
+    pub(super) obp1: Palette,
+    // internal line counter of the window, and whether LY matched WY in the current frame.
+    pub(super) window_line: u8,
+    pub(super) window_triggered: bool,
+    // raw colour indices of the background and window in the current scanline, before the palette.
+    pub(super) bg_line: [u8; SCREEN_WIDTH as usize],
+    pub(super) framebuffer: [(u8, u8, u8); (SCREEN_WIDTH * SCREEN_HEIGHT) as usize],
+    // draws the pixels during mode 3, only taken out while it's running.
+    renderer: Option<Box<dyn Renderer>>,
+    // whether CPU accesses to VRAM and OAM are blocked while the PPU uses them.
+    access_locks: bool,
 }
 
 impl GPU {
@@ -25,156 +54,201 @@ impl GPU {
             line: 0,
             lyc: 0,
             cycles: 0,
+            stat_line: false,
             vram: VideoMemory::new(),
             lcdc: LCDControl::new(),
             lcdp: LCDPosition::new(),
             lcds: LCDStatus::new(),
+            bgp: Palette::new(0xFC),
+            obp0: Palette::new(0xFF),
+            obp1: Palette::new(0xFF),
+            window_line: 0,
+            window_triggered: false,
+            bg_line: [0; SCREEN_WIDTH as usize],
             framebuffer: [(0, 0, 0); (SCREEN_WIDTH * SCREEN_HEIGHT) as usize],
+            renderer: Some(Box::new(ScanlineRenderer::new())),
+            access_locks: true,
+        }
+    }
+
+    /// Replaces the renderer used to draw the visible lines, the scanline renderer is used by default.
+    pub fn set_renderer(&mut self, renderer: Box<dyn Renderer>) {
+        self.renderer = Some(renderer);
+
+        // a line in progress is restarted from scratch by the new renderer
+        if self.lcds.mode == Mode::VRAMRead {
+            self.with_renderer(|renderer, gpu| renderer.start_line(gpu));
         }
     }
 
+    /// Runs `f` with the renderer, which needs mutable access to the rest of the GPU.
+    fn with_renderer<R, F: FnOnce(&mut dyn Renderer, &mut GPU) -> R>(&mut self, f: F) -> R {
+        let mut renderer = self.renderer.take().expect("The renderer is already running");
+        let result = f(&mut *renderer, self);
+        self.renderer = Some(renderer);
+        result
+    }
+
     /// Emulates the GPU.
     /// This function should be called after an instruction is executed by the CPU,
     /// `delta` is the number of cycles passed from the last instruction.
-    pub fn emulate(&mut self, delta: u32) {
+    /// Interrupts raised by the GPU are requested through `interrupts`.
+    ///
+    /// Each scanline takes 456 cycles: 80 in mode 2 (OAM search), 172 or more in mode 3 (pixel transfer)
+    /// depending on the renderer, and the rest in mode 0 (HBlank). Lines 144 to 153 are spent in mode 1 (VBlank).
+    pub fn emulate(&mut self, delta: u32, interrupts: &mut InterruptController) {
 
         // If screen is disabled
         if !self.lcdc.lcd_enable {
             return;
         }
 
-        self.cycles += delta;
+        for _ in 0..delta {
+            self.tick(interrupts);
+        }
+    }
+
+    /// Advances the GPU by a single cycle.
+    fn tick(&mut self, interrupts: &mut InterruptController) {
+        self.cycles += 1;
 
         match self.lcds.mode {
-            Mode::OAMRead => {
-                if self.cycles >= 80 {
-                    self.cycles = 0;
-                    self.lcds.mode = Mode::VRAMRead;
-                }
+            Mode::OAMRead if self.cycles == OAM_SEARCH_CYCLES => {
+                self.lcds.mode = Mode::VRAMRead;
+                self.with_renderer(|renderer, gpu| renderer.start_line(gpu));
             }
             Mode::VRAMRead => {
-                if self.cycles >= 172 {
-                    self.cycles = 0;
+                // the renderer decides when the scanline is done
+                if self.with_renderer(|renderer, gpu| renderer.tick(gpu)) {
                     self.lcds.mode = Mode::HBlank;
-
-                    // scanline is done, write it to framebuffer
-                    self.render_scanline();
                 }
             }
-            Mode::HBlank => {
-                if self.cycles >= 204 {
-                    self.cycles = 0;
-                    self.line += 1;
-
-                    self.lcds.mode = if self.line == 143 {
-                        // TODO: push framebuffer data to frontend now (interrupt)
-                        self.lcds.vblank_irq = true;
-                        Mode::VBlank
-
-                    } else {
-                        Mode::OAMRead
-                    };
+            Mode::HBlank | Mode::VBlank if self.cycles == LINE_CYCLES => {
+                self.cycles = 0;
+                self.line = if self.line == LAST_LINE { 0 } else { self.line + 1 };
+
+                if self.line == SCREEN_HEIGHT as u8 {
+                    self.lcds.mode = Mode::VBlank;
+                    interrupts.request(Interrupt::VBlank);
+                    self.reset_window();
+                } else if self.line < SCREEN_HEIGHT as u8 {
+                    self.lcds.mode = Mode::OAMRead;
                 }
+
+                self.update_coincidence();
             }
-            Mode::VBlank => {
-                if self.cycles >= 456 {
-                    self.cycles = 0;
-                    self.line += 1;
-
-                    if self.line > 153 {
-                        self.lcds.mode = Mode::OAMRead;
-                        self.line = 0;
-                    }
-                }
+            Mode::VBlank if self.line == LAST_LINE && self.cycles == LAST_LINE_LY_CYCLES => {
+                self.update_coincidence();
             }
+            _ => {}
         }
+
+        self.update_stat_line(interrupts);
     }
 
-    /// Renders a single scanline to the framebuffer, from the internal tile data.
-    fn render_scanline(&mut self) {
-        self.render_background();
-        self.render_window();
-        self.render_sprites();
+    /// Returns the value of LY. Most of the last line already reads as line 0,
+    /// so LYC=0 matches before the next frame starts.
+    fn ly(&self) -> u8 {
+        if self.line == LAST_LINE && self.cycles >= LAST_LINE_LY_CYCLES {
+            0
+        } else {
+            self.line
+        }
     }
 
-    /// Retrieves Tile information from the VRAM.
-    /// A tile is held in 16 bytes in the VRAM, enough information for 64 pixels (8x8 matrix, 2 bits per pixel).
-    /// When selecting tiles from Set #1, the index 0 represents tile -128 (equal to tile 128 from Set #0)
-    fn get_tile(&self, set: TileSelector, tile_index: usize) -> Tile {
-        // Set1 starts after 128 tiles, or after 16 * 128 = 2048 (0x800) bytes
-        let offset = if let TileSelector::Set1 = set { 0x800 } else { 0 };
-        let start_addr = (offset + tile_index) * 0x10;
-        let end_addr = start_addr + 0x10;
+    /// Updates the coincidence flag, comparing LY and LYC.
+    fn update_coincidence(&mut self) {
+        self.lcds.coincidence_flag = self.ly() == self.lyc;
+    }
 
-        let mut addr = start_addr;
-        let mut pixels = [GBColor::Off; 64];
+    /// Moves the PPU `cycles` cycles into the VBlank line `line`, used to start from the state
+    /// left by the boot ROM. The LCD must be on.
+    pub fn set_vblank_position(&mut self, line: u8, cycles: u32) {
+        self.line = line;
+        self.cycles = cycles;
+        self.lcds.mode = Mode::VBlank;
+        self.update_coincidence();
+    }
 
-        // this is basically a for with step 2. Iterates 8 times (two bytes read at a time).
-        while addr < end_addr {
-            let (low, high) = (self.vram.data[addr], self.vram.data[addr + 1]);
+    /// The STAT interrupt sources are ORed together in a single line, and the interrupt is only
+    /// requested when the line goes from low to high. While one source keeps the line high,
+    /// the others can't trigger a new interrupt ("STAT blocking").
+    fn update_stat_line(&mut self, interrupts: &mut InterruptController) {
+        let mode_source = match self.lcds.mode {
+            Mode::HBlank => self.lcds.hblank_irq,
+            Mode::VBlank => self.lcds.vblank_irq,
+            Mode::OAMRead => self.lcds.oam_irq,
+            Mode::VRAMRead => false,
+        };
+        let stat_line = mode_source || (self.lcds.coincidence_irq && self.lcds.coincidence_flag);
 
-            for i in 0..8 {
-                let low_bit = (low >> i) & 0x01;
-                let high_bit = (high >> i) & 0x01;
+        if stat_line && !self.stat_line {
+            interrupts.request(Interrupt::LCDStat);
+        }
 
-                let pixel_value = GBColor::from_u8(low_bit + high_bit * 2);
-                // px 0..7, then 8..15
-                let pixel_addr = (addr / 2) * 8 + i;
-                pixels[pixel_addr] = pixel_value;
-            }
+        self.stat_line = stat_line;
+    }
 
-            addr += 2;
+    /// Writes LCDC, turning the LCD on or off if bit 7 changes.
+    /// While off, LY is 0 and STAT reports mode 0. When turned back on, the first line starts from scratch.
+    fn write_lcdc(&mut self, data: u8) {
+        let was_enabled = self.lcdc.lcd_enable;
+        self.lcdc.set_from_u8(data);
+
+        if was_enabled && !self.lcdc.lcd_enable {
+            self.line = 0;
+            self.cycles = 0;
+            self.lcds.mode = Mode::HBlank;
+            self.stat_line = false;
+        } else if !was_enabled && self.lcdc.lcd_enable {
+            self.line = 0;
+            self.cycles = 0;
+            self.lcds.mode = Mode::OAMRead;
+            self.update_coincidence();
+            self.reset_window();
         }
-
-        Tile { pixels: pixels }
     }
 
-    /// Retrieves a single pixel from a given tile index.
-    /// The pixel index is a number between 0 and 63.
-    fn get_tile_pixel(&self, set: TileSelector, tile_index: usize, pixel_index: usize) -> GBColor {
-        let offset = if let TileSelector::Set1 = set { 0x800 } else { 0 };
-        let start_addr = (offset + tile_index) * 0x10;
+    /// Resets the window state at the start of a frame.
+    fn reset_window(&mut self) {
+        self.window_line = 0;
+        self.window_triggered = false;
+    }
 
-        let addr = start_addr + ((pixel_index / 8) as usize) * 2;
-        let (low, high) = (self.vram.data[addr], self.vram.data[addr + 1]);
-        let i = pixel_index % 8;
-        let low_bit = (low >> i) & 0x01;
-        let high_bit = (high >> i) & 0x01;
+    /// Starts showing the window from the first line where LY=WY in the frame.
+    pub(super) fn update_window_trigger(&mut self) {
+        if self.line == self.lcdp.window_y {
+            self.window_triggered = true;
+        }
+    }
 
-        GBColor::from_u8(low_bit + high_bit * 2)
+    /// Renders a single scanline to the framebuffer, from the internal tile data.
+    pub(super) fn render_scanline(&mut self) {
+        self.render_background();
+        self.render_window();
+        self.render_sprites();
     }
 
     /// Retrieves a slice of the framebuffer.
     pub fn get_framebuffer(&mut self) -> &[(u8, u8, u8)]{
-        if self.lcds.vblank_irq {
-            self.lcds.vblank_irq = false;
-        }
-
         &self.framebuffer
     }
 
     /// Renders the current window.
     /// The window is an alternate background area that can be rendered above the normal background.
     ///
-    /// The window becomes visible (if enabled) when positions are set in range WX=0..166, WY=0..143.
-    /// A position of WX=7, WY=0 locates the window at upper left, it is then completely covering normal background.
+    /// The window is shown from the first line where LY=WY in the frame, starting at X=WX-7.
+    /// With WX<7 the leftmost columns of the window are cut off, WX>166 hides it.
+    /// The window has its own line counter, incremented only on lines where it was drawn,
+    /// so hiding it for some lines makes it resume from where it stopped.
     fn render_window(&mut self) {
-        // get window X and Y, X is offset by 7 so we subtract 7
-        let wy = self.lcdp.window_y;
-        let wx = self.lcdp.window_x as i32 - 7;
-
-        // continue rendering only if wx/wy are in range (0 to 160 and 0 to 144)
-        // also, the current line being rendered must be inside the window (line < wy)
-        match (wx, wy) {
-            (0..=159, 0..=143) if self.line < wy => {}
-            (_, _) => { return; }
-        };
+        self.update_window_trigger();
 
-        // Tile data start offset (0=8800-97FF, 1=8000-8FFF)
-        let tiles_start = match self.lcdc.bgw_tile_data_select {
-            TileSelector::Set0 => 0 as u16,
-            TileSelector::Set1 => 0x800,
-        };
+        // on DMG, LCDC bit 0 hides the window too
+        let wx = self.lcdp.window_x as i32;
+        if !self.lcdc.window_enable || !self.lcdc.bg_enable || !self.window_triggered || wx > 166 {
+            return;
+        }
 
         // Window tile map start (0=9800-9BFF, 1=9C00-9FFF)
         let tilemap_start = match self.lcdc.window_tile_map {
@@ -182,297 +256,780 @@ impl GPU {
             TileSelector::Set1 => 0x1C00,
         };
 
-        // offset for the tile map given by the current line value
-        let tilemap_y_offset = ((self.line / 8) * 32) as usize;
-        // y value inside a tile identified by the current line
-        let y_offset = ((self.line % 8) * 2) as u16;
-
-        for x in 0..32 {
-
-            // Read the tile index from the map in memory
-            let tile_index = match self.lcdc.window_tile_map {
-                TileSelector::Set0 => self.vram.data[tilemap_start + tilemap_y_offset + x],
-                TileSelector::Set1 => {
-                    // set 1 is indexed from -128 to 127, indexes are signed bytes
-                    let tile_signed = self.vram.data[tilemap_start + tilemap_y_offset + x] as i16;
-                    (tile_signed + 128) as u8
-                },
-            };
+        // offset for the tile map given by the window line, and y value inside the tile
+        let tilemap_y_offset = (self.window_line as usize / 8) * 32;
+        let y_offset = (self.window_line as usize % 8) * 2;
 
-            // offset in the map for x value, it's x (current x in tile) times 8 (tile width)
-            let map_offset = x * 8;
+        let fb_offset = self.line as usize * SCREEN_WIDTH as usize;
+        let start_x = wx - 7;
 
-            // index of a tile in a byte array (tiles are 16 bytes long)
-            let tile_byte_idx = (tile_index * 16) as u16;
+        for x in start_x.max(0)..SCREEN_WIDTH {
+            let window_x = (x - start_x) as usize;
 
-            // actual address for tile data
-            let tile_address = tiles_start + tile_byte_idx + y_offset;
+            let tile_index = self.vram.data[tilemap_start + tilemap_y_offset + window_x / 8];
+            let tile_address = self.tile_data_address(tile_index) + y_offset;
+            let low_byte = self.vram.data[tile_address];
+            let high_byte = self.vram.data[tile_address + 1];
 
-            let high_byte = self.vram.data[tile_address as usize];
-            let low_byte = self.vram.data[tile_address as usize + 1];
+            // bit 7 is the leftmost pixel
+            let bit = 7 - window_x % 8;
+            let pixel = if is_set(low_byte, bit) { 1 } else { 0 } |
+                        if is_set(high_byte, bit) { 2 } else { 0 };
 
-            // compute each of the 8 pixels in the 2 bytes, starting from bit 7
-            let mut pixel_idx = 7;
-            while pixel_idx >= 0 {
-                // the offset given by the windowX
-                let buffer_x_offset = map_offset as i32 + pixel_idx + wx;
-
-                let pixel = if is_set(high_byte, pixel_idx as usize) { 1 } else { 0 } |
-                            if is_set(low_byte, pixel_idx as usize) { 2 } else { 0 };
-
-                // final position of this pixel offset by line * line_width
-                let position = SCREEN_WIDTH as usize * self.line as usize + buffer_x_offset as usize;
-
-                let color = GBColor::from_u8(pixel);
-                self.framebuffer[position] = color.to_u8u8u8();
+            self.bg_line[x as usize] = pixel;
+            self.framebuffer[fb_offset + x as usize] = self.bgp.apply(pixel).to_u8u8u8();
+        }
 
-                pixel_idx -= 1;
-            }
+        self.window_line += 1;
+    }
 
-            // TODO update line value?
+    /// Returns the VRAM address of a background or window tile, using the addressing mode
+    /// selected by LCDC bit 4. With 8000 addressing indexes are unsigned, with 8800 addressing
+    /// they're signed and relative to 0x9000.
+    pub(super) fn tile_data_address(&self, tile_index: u8) -> usize {
+        match self.lcdc.bgw_tile_data_select {
+            TileSelector::Set1 => tile_index as usize * 16,
+            TileSelector::Set0 => (0x1000 + (tile_index as i8 as i32) * 16) as usize,
         }
     }
 
+    /// Selects the sprites on the current line, as the PPU does during the OAM search.
+    /// OAM is scanned in order and only the first 10 sprites overlapping the line are kept,
+    /// even if some of them are outside of the screen horizontally.
+    pub(super) fn scan_oam(&self) -> Vec<Sprite> {
+        let height = self.lcdc.sprite_size as i32;
+        let line = self.line as i32;
+
+        (0..40)
+            .map(|i| Sprite::from_oam(&self.vram.oam, i))
+            .filter(|sprite| line >= sprite.y && line < sprite.y + height)
+            .take(MAX_SPRITES_PER_LINE)
+            .collect()
+    }
 
+    /// Renders the sprites on the current line.
+    /// On DMG, when sprites overlap the one with the smaller X is drawn on top, or the one that
+    /// comes first in OAM if they have the same X. The winning pixel is then hidden by the
+    /// background if the sprite has the priority flag set and the background colour is not 0.
     fn render_sprites(&mut self) {
 
         if !self.lcdc.sprite_enable {
             return;
         }
 
-        let sprite_height = self.lcdc.sprite_size as u8;
-        let line_width = self.line as i32 * SCREEN_WIDTH;
-
-        // 40 sprites can be displayed
-        let mut sprite = 39;
-        while sprite >= 0 {
-            let sprite_offset = sprite * 4;
-
-            // Sprite data is 4 bytes with the following information
-            //   Byte0  Y position on the screen
-            //   Byte1  X position on the screen
-            //   Byte2  Tile map index, like with tiles an index from 0 to 255
-            //   Byte3  Flags:
-            //     Bit7  Priority
-            //       Sprite is displayed in front of the window if this bit is set to 1.
-            //       Otherwise, sprite is shown behind the window but in front of the background.
-            //     Bit6  Y flip
-            //       Sprite pattern is flipped vertically if this bit is set to 1.
-            //     Bit5  X flip
-            //       Sprite pattern is flipped horizontally if this bit is set to 1.
-            //     Bit4  Palette number
-            //       Use colors from OBJ1PAL if this bit is set and from OBJ0PAL otherwise.
-
-
-            let sprite_y = self.vram.oam[sprite_offset as usize] as i32 - 16;
-            let sprite_x = self.vram.oam[(sprite_offset + 1) as usize] as i32 - 8;
-
-            // skip sprite if outside boundaries
-            if (sprite_y > self.line as i32) || (sprite_y + sprite_height as i32) <= self.line as i32 {
-                continue;
-            }
-
-            if (sprite_x < -7) || (sprite_x >= SCREEN_WIDTH) {
-                continue;
-            }
+        let height = self.lcdc.sprite_size as i32;
+        let line_offset = self.line as usize * SCREEN_WIDTH as usize;
 
-            // TODO what if sprite height is 16? any changes?
-            let sprite_tile = self.vram.oam[(sprite_offset + 2) as usize];
-            let sprite_flags = self.vram.oam[(sprite_offset + 3) as usize];
+        let mut sprites = self.scan_oam();
+        sprites.sort_by_key(|sprite| (sprite.x, sprite.index));
 
-            // TODO refactor this into a struct?
-            let sprite_palette = if is_set(sprite_flags, 4) { 1 } else { 0 };
-            let x_flip = is_set(sprite_flags, 5);
-            let y_flip = is_set(sprite_flags, 6);
-            let over_window = is_set(sprite_flags, 7);
+        // pixels already taken by a sprite with higher priority
+        let mut drawn = [false; SCREEN_WIDTH as usize];
 
-            let pixel_y = if y_flip {
-                // sprite is flipped vertically
-                (sprite_height as i32 - 1) - (self.line as i32 - sprite_y)
+        for sprite in sprites {
+            let row = if sprite.y_flip {
+                height - 1 - (self.line as i32 - sprite.y)
             } else {
-                self.line as i32- sprite_y
+                self.line as i32 - sprite.y
             };
 
-            // offset in tile map due to y position
-            let pixel_y_offset = if sprite_height == 16 && pixel_y >= 8 {
-                ((pixel_y - 8) * 2) + 16
-            } else {
-                pixel_y * 2
-            };
+            // in 8x16 mode, the top tile is the even one and the bottom tile is the next one
+            let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile } as usize;
+            let tile_address = tile * 16 + row as usize * 2;
+            let low_byte = self.vram.data[tile_address];
+            let high_byte = self.vram.data[tile_address + 1];
 
-            let tile_address = sprite_tile as i32 + pixel_y_offset as i32;
-            // retrieve the pixel data
-            let high_byte = self.vram.data[tile_address as usize];
-            let low_byte = self.vram.data[(tile_address + 1) as usize];
+            let palette = if sprite.use_obp1 { self.obp1 } else { self.obp0 };
 
-            // compute each pixel and render
             for x in 0..8 {
-                let pixel_idx = if x_flip { x } else { 7 - x };
+                let x_pos = sprite.x + x;
+                if x_pos < 0 || x_pos >= SCREEN_WIDTH || drawn[x_pos as usize] {
+                    continue;
+                }
 
-                let pixel = if is_set(high_byte, pixel_idx as usize) { 1 } else { 0 } |
-                            if is_set(low_byte, pixel_idx as usize) { 2 } else { 0 };
+                // bit 7 is the leftmost pixel
+                let bit = if sprite.x_flip { x } else { 7 - x } as usize;
+                let pixel = if is_set(low_byte, bit) { 1 } else { 0 } |
+                            if is_set(high_byte, bit) { 2 } else { 0 };
 
-                // skip transparent pixels (value == 0)
+                // colour 0 is transparent, the pixel can still be taken by another sprite
                 if pixel == 0 {
                     continue;
                 }
 
-                // skip pixels outside boundaries
-                let x_pos = x + sprite_x;
+                drawn[x_pos as usize] = true;
 
-                if x_pos < 0 || x_pos >= 144 {
+                if sprite.behind_bg && self.bg_line[x_pos as usize] != 0 {
                     continue;
                 }
 
-                // index in the framebuffer
-                let position = line_width as i32 + x_pos;
-
-                // TODO skip pixel if it is above the BG (need to keep BG data)
-                // if !over_window {
-                //
-                // }
-
-                // TODO: read palette from memory (0xFF49 for OBJ1, 0xFF48 for OBJ0)
-                let color = GBColor::from_u8(pixel);
-                self.framebuffer[position as usize] = color.to_u8u8u8();
+                self.framebuffer[line_offset + x_pos as usize] = palette.apply(pixel).to_u8u8u8();
             }
-
-            sprite -= 1;
         }
     }
 
-    /// Renders the background
+    /// Renders the background on the current line.
+    /// The background is a 256x256 pixels map of tiles, SCX and SCY select the visible area
+    /// and wrap around the edges of the map.
+    /// On DMG, clearing LCDC bit 0 makes the background (and window) white.
     fn render_background(&mut self) {
-        // map offset starts from 0x1C00 (for tileset #1) or 0x1800 (for tileset #0)
-        let mut y_offset : u16 = if let TileSelector::Set1 = self.lcdc.bg_tile_map { 0x1C00 } else { 0x1800 };
-
-        // add vertical offset (y_scroll plus the scanline we are at right now)
-        // line and scroll_y are on a per pixel basis, shifting right by 3 gets the tile index offset
-        y_offset += ((self.line as u8).wrapping_add(self.lcdp.scroll_y) >> 3) as u16;
+        let fb_offset = (self.line as usize) * SCREEN_WIDTH as usize;
 
-        // same for x tile offset
-        let mut x_offset = (self.lcdp.scroll_x >> 3) as u16;
+        if !self.lcdc.bg_enable {
+            for x in 0..SCREEN_WIDTH as usize {
+                self.bg_line[x] = 0;
+                self.framebuffer[fb_offset + x] = GBColor::Off.to_u8u8u8();
+            }
+            return;
+        }
 
-        // x,y are the 3 least significant bits in the offsets
-        // they represent the coordinates of the pixel inside a tile
-        let y = (y_offset & 0x07) as usize;
-        let mut x = (x_offset & 0x07) as usize;
+        // BG tile map start (0=9800-9BFF, 1=9C00-9FFF)
+        let tilemap_start = match self.lcdc.bg_tile_map {
+            TileSelector::Set0 => 0x1800,
+            TileSelector::Set1 => 0x1C00,
+        };
 
-        // starting scanline where we will write on the framebuffer
-        let fb_offset = (self.line as usize) * SCREEN_WIDTH as usize;
+        // the line in the 256x256 map, its row of tiles and the y value inside a tile
+        let y = self.line.wrapping_add(self.lcdp.scroll_y) as usize;
+        let tilemap_y_offset = (y / 8) * 32;
+        let y_offset = (y % 8) * 2;
 
-        // read the tile index from the vram at the computed offset
-        let mut tile_index = self.read_vram((y_offset + x_offset) as usize);
+        for x in 0..SCREEN_WIDTH as usize {
+            let map_x = (x as u8).wrapping_add(self.lcdp.scroll_x) as usize;
 
-        // compute each of the 160 pixels in a scanline
-        for i in 0..SCREEN_WIDTH {
-            // TODO: maybe load tile lines instead of single pixels, less function calls.
-            let pixel = self.get_tile_pixel(self.lcdc.bg_tile_map, tile_index as usize, x + y);
+            let tile_index = self.vram.data[tilemap_start + tilemap_y_offset + map_x / 8];
+            let tile_address = self.tile_data_address(tile_index) + y_offset;
+            let low_byte = self.vram.data[tile_address];
+            let high_byte = self.vram.data[tile_address + 1];
 
-            self.framebuffer[fb_offset + i as usize] = pixel.to_u8u8u8();
-            x += 1;
+            // bit 7 is the leftmost pixel
+            let bit = 7 - map_x % 8;
+            let pixel = if is_set(low_byte, bit) { 1 } else { 0 } |
+                        if is_set(high_byte, bit) { 2 } else { 0 };
 
-            if x == 8 {
-                // reached end of current tile, go to the next one (right)
-                x = 0;
-                // tiles wrap horizontally (every 32)
-                x_offset = (x_offset + 1) % 32;
-                tile_index = self.read_vram((y_offset + x_offset) as usize);
-            }
+            self.bg_line[x] = pixel;
+            self.framebuffer[fb_offset + x] = self.bgp.apply(pixel).to_u8u8u8();
         }
+    }
 
+    /// Allows or denies CPU accesses to VRAM and OAM while the PPU is using them, see `write_vram`.
+    /// Disabling the locks is useful for debugging, it's not possible on real hardware.
+    pub fn set_access_locks(&mut self, enabled: bool) {
+        self.access_locks = enabled;
     }
 
+    /// The CPU can't access VRAM during mode 3, while the PPU reads it.
+    fn vram_locked(&self) -> bool {
+        self.access_locks && self.lcds.mode == Mode::VRAMRead
+    }
+
+    /// The CPU can't access OAM during modes 2 and 3, while the PPU reads it.
+    fn oam_locked(&self) -> bool {
+        self.access_locks && (self.lcds.mode == Mode::OAMRead || self.lcds.mode == Mode::VRAMRead)
+    }
+
+    /// Writes VRAM on behalf of the CPU. The write is dropped if VRAM is locked.
     pub fn write_vram(&mut self, addr: usize, value: u8) {
-        // if let Mode::VRAMRead = self.lcds.mode { panic!("Attempted write to VRAM during VRAMRead mode") };
+        if self.vram_locked() {
+            return;
+        }
         self.vram.data[addr] = value;
     }
 
+    /// Reads VRAM on behalf of the CPU, returns 0xFF if VRAM is locked.
     pub fn read_vram(&self, addr: usize) -> u8 {
-        // if let Mode::VRAMRead = self.lcds.mode { panic!("Attempted access to VRAM during VRAMRead mode") };
+        if self.vram_locked() {
+            return 0xFF;
+        }
         self.vram.data[addr]
     }
 
+    /// Writes OAM on behalf of the CPU. The write is dropped if OAM is locked.
     pub fn write_oam(&mut self, addr: usize, value: u8) {
-        // if let Mode::OAMRead = self.lcds.mode { panic!("Attempted write to OAM during OAMRead mode") };
+        if self.oam_locked() {
+            return;
+        }
         self.vram.oam[addr] = value;
     }
 
+    /// Reads OAM on behalf of the CPU, returns 0xFF if OAM is locked.
     pub fn read_oam(&self, addr: usize) -> u8 {
-        // if let Mode::OAMRead = self.lcds.mode { panic!("Attempted access to OAM during OAMRead mode") };
+        if self.oam_locked() {
+            return 0xFF;
+        }
         self.vram.oam[addr]
     }
 
+    /// Writes OAM on behalf of the OAM DMA, which is not affected by the locks.
+    pub fn dma_write_oam(&mut self, addr: usize, value: u8) {
+        self.vram.oam[addr] = value;
+    }
+
     pub fn read_register(&self, addr: usize) -> u8 {
         match addr {
             0xFF40 => self.lcdc.as_u8(), // LCDC
-            0xFF41 => self.lcds.to_u8(), // LCDStat
-            0xFF42 => self.lcdp.scroll_x,
-            0xFF43 => self.lcdp.scroll_y,
-            0xFF44 => self.line, // current scanline
+            0xFF41 => 0x80 | self.lcds.to_u8(), // LCDStat, bit 7 is unused
+            0xFF42 => self.lcdp.scroll_y,
+            0xFF43 => self.lcdp.scroll_x,
+            0xFF44 => self.ly(), // current scanline
             0xFF45 => self.lyc,
-            0xFF47 => panic!("Palette is write only!"),
+            0xFF47 => self.bgp.value,
+            0xFF48 => self.obp0.value,
+            0xFF49 => self.obp1.value,
+            0xFF4A => self.lcdp.window_y,
+            0xFF4B => self.lcdp.window_x,
             _ => panic!("Attempted GPU register access with addr {:4x}", addr),
         }
     }
 
     pub fn write_register(&mut self, addr: usize, data: u8) {
         match addr {
-            0xFF40 => self.lcdc.set_from_u8(data), // LCDC
+            0xFF40 => self.write_lcdc(data), // LCDC
             0xFF41 => self.lcds.set_from_u8(data), // LCDStat
-            0xFF42 => { self.lcdp.scroll_x = data },
-            0xFF43 => { self.lcdp.scroll_y = data },
-            0xFF44 => { self.line = data }, // current scanline
-            0xFF45 => { self.lyc = data },
-            0xFF47 => {
-                // TODO: figure palette writing (is it needed for CGB only?)
-             },
+            0xFF42 => { self.lcdp.scroll_y = data },
+            0xFF43 => { self.lcdp.scroll_x = data },
+            0xFF44 => {}, // current scanline, read only
+            0xFF45 => {
+                self.lyc = data;
+                self.update_coincidence();
+            },
+            0xFF47 => self.bgp = Palette::new(data),
+            0xFF48 => self.obp0 = Palette::new(data),
+            0xFF49 => self.obp1 = Palette::new(data),
+            0xFF4A => self.lcdp.window_y = data,
+            0xFF4B => self.lcdp.window_x = data,
             _ => panic!("Attempted GPU register write with addr {:4x}", addr),
         };
     }
 }
 
+#[cfg(test)]
+fn enabled_gpu() -> GPU {
+    let mut gpu = GPU::new();
+    gpu.write_register(0xFF40, 0x80);
+    gpu
+}
+
+#[test]
+fn mode_timing() {
+    let mut gpu = enabled_gpu();
+    let mut interrupts = InterruptController::new();
+
+    assert_eq!(2, gpu.read_register(0xFF41) & 0x03);
+    gpu.emulate(79, &mut interrupts);
+    assert_eq!(2, gpu.read_register(0xFF41) & 0x03);
+    gpu.emulate(1, &mut interrupts);
+    assert_eq!(3, gpu.read_register(0xFF41) & 0x03);
+    gpu.emulate(172, &mut interrupts);
+    assert_eq!(0, gpu.read_register(0xFF41) & 0x03);
+
+    // leftover cycles are carried to the next line
+    gpu.emulate(204 + 10, &mut interrupts);
+    assert_eq!(1, gpu.read_register(0xFF44));
+    assert_eq!(2, gpu.read_register(0xFF41) & 0x03);
+    gpu.emulate(70, &mut interrupts);
+    assert_eq!(3, gpu.read_register(0xFF41) & 0x03);
+}
+
+#[test]
+fn vblank_interrupt() {
+    let mut gpu = enabled_gpu();
+    let mut interrupts = InterruptController::new();
+    interrupts.write_enable(0xFF);
+
+    gpu.emulate(143 * 456, &mut interrupts);
+    assert_eq!(143, gpu.read_register(0xFF44));
+    assert_eq!(0, interrupts.pending());
+
+    gpu.emulate(456, &mut interrupts);
+    assert_eq!(144, gpu.read_register(0xFF44));
+    assert_eq!(1, gpu.read_register(0xFF41) & 0x03);
+    assert_eq!(Interrupt::VBlank as u8, interrupts.pending());
+
+    // a full frame is 154 lines
+    gpu.emulate(10 * 456, &mut interrupts);
+    assert_eq!(0, gpu.read_register(0xFF44));
+    assert_eq!(2, gpu.read_register(0xFF41) & 0x03);
+}
+
+#[test]
+fn last_line_reads_as_0() {
+    let mut gpu = enabled_gpu();
+    let mut interrupts = InterruptController::new();
+    interrupts.write_enable(0xFF);
+    gpu.write_register(0xFF41, 0x40);
+
+    gpu.emulate(153 * 456, &mut interrupts);
+    assert_eq!(153, gpu.read_register(0xFF44));
+    interrupts.acknowledge(Interrupt::VBlank);
+
+    // LYC=0 matches on the last line, and again on line 0
+    gpu.emulate(4, &mut interrupts);
+    assert_eq!(0, gpu.read_register(0xFF44));
+    assert_eq!(0xC5, gpu.read_register(0xFF41));
+    assert_eq!(Interrupt::LCDStat as u8, interrupts.pending());
+
+    gpu.emulate(452, &mut interrupts);
+    assert_eq!(0, gpu.read_register(0xFF44));
+    assert_eq!(0xC6, gpu.read_register(0xFF41));
+}
+
+#[test]
+fn lyc_interrupt() {
+    let mut gpu = enabled_gpu();
+    let mut interrupts = InterruptController::new();
+    interrupts.write_enable(0xFF);
+
+    gpu.write_register(0xFF45, 2);
+    gpu.write_register(0xFF41, 0x40);
+
+    gpu.emulate(2 * 456 - 1, &mut interrupts);
+    assert_eq!(0, interrupts.pending());
+    gpu.emulate(1, &mut interrupts);
+    assert_eq!(Interrupt::LCDStat as u8, interrupts.pending());
+    assert_eq!(0xC6, gpu.read_register(0xFF41));
+
+    // the flag is cleared on the next line
+    gpu.emulate(456, &mut interrupts);
+    assert_eq!(0xC2, gpu.read_register(0xFF41));
+}
+
+#[test]
+fn stat_blocking() {
+    let mut gpu = enabled_gpu();
+    let mut interrupts = InterruptController::new();
+    interrupts.write_enable(0xFF);
+
+    // HBlank and OAM sources, the line stays high from HBlank to the next OAM search
+    gpu.write_register(0xFF41, 0x28);
+    gpu.emulate(252, &mut interrupts);
+    assert_eq!(Interrupt::LCDStat as u8, interrupts.pending());
+
+    interrupts.acknowledge(Interrupt::LCDStat);
+    gpu.emulate(204, &mut interrupts);
+    assert_eq!(1, gpu.read_register(0xFF44));
+    assert_eq!(0, interrupts.pending());
+}
+
+#[test]
+fn lcd_off() {
+    let mut gpu = enabled_gpu();
+    let mut interrupts = InterruptController::new();
+
+    gpu.emulate(10 * 456 + 100, &mut interrupts);
+    assert_eq!(10, gpu.read_register(0xFF44));
+
+    gpu.write_register(0xFF40, 0x00);
+    assert_eq!(0, gpu.read_register(0xFF44));
+    assert_eq!(0x80, gpu.read_register(0xFF41));
+
+    // nothing happens while off
+    gpu.emulate(1000, &mut interrupts);
+    assert_eq!(0, gpu.read_register(0xFF44));
+
+    gpu.write_register(0xFF40, 0x80);
+    assert_eq!(2, gpu.read_register(0xFF41) & 0x03);
+}
+
+#[test]
+fn palette_registers() {
+    let mut gpu = GPU::new();
+
+    gpu.write_register(0xFF47, 0xE4);
+    gpu.write_register(0xFF48, 0xD2);
+    gpu.write_register(0xFF49, 0x1B);
+    assert_eq!(0xE4, gpu.read_register(0xFF47));
+    assert_eq!(0xD2, gpu.read_register(0xFF48));
+    assert_eq!(0x1B, gpu.read_register(0xFF49));
+}
+
+#[test]
+fn background_uses_palette() {
+    let mut gpu = GPU::new();
+    let mut interrupts = InterruptController::new();
+
+    // first row of tile 0 is colour index 3
+    gpu.write_vram(0, 0xFF);
+    gpu.write_vram(1, 0xFF);
+    // index 3 is white, the others are black
+    gpu.write_register(0xFF47, 0x3F);
+    gpu.write_register(0xFF40, 0x91);
+
+    gpu.emulate(456, &mut interrupts);
+    assert_eq!((255, 255, 255), gpu.framebuffer[0]);
+    assert_eq!(3, gpu.bg_line[0]);
+}
+
+#[cfg(test)]
+fn sprite_gpu() -> GPU {
+    let mut gpu = GPU::new();
+    // LCD, BG and sprites on
+    gpu.write_register(0xFF40, 0x83);
+    gpu.write_register(0xFF47, 0xE4);
+    gpu.write_register(0xFF48, 0xE4);
+    gpu.write_register(0xFF49, 0x1B);
+
+    // tile 1 is solid colour 1, tile 2 solid colour 2, tile 3 solid colour 3
+    for row in 0..8 {
+        gpu.write_vram(16 + row * 2, 0xFF);
+        gpu.write_vram(32 + row * 2 + 1, 0xFF);
+        gpu.write_vram(48 + row * 2, 0xFF);
+        gpu.write_vram(48 + row * 2 + 1, 0xFF);
+    }
+    gpu
+}
+
+#[cfg(test)]
+fn write_sprite(gpu: &mut GPU, index: usize, y: u8, x: u8, tile: u8, flags: u8) {
+    // OAM is locked for the CPU while the LCD is on
+    gpu.vram.oam[index * 4..index * 4 + 4].copy_from_slice(&[y, x, tile, flags]);
+}
+
+#[test]
+fn sprite_tile_and_palette() {
+    let mut gpu = sprite_gpu();
+    write_sprite(&mut gpu, 0, 16, 8, 2, 0x00);
+    write_sprite(&mut gpu, 1, 16, 16, 2, 0x10);
+    // partially off the right edge
+    write_sprite(&mut gpu, 2, 16, 164, 1, 0x00);
+
+    gpu.render_scanline();
+    assert_eq!(GBColor::On66.to_u8u8u8(), gpu.framebuffer[0]);
+    assert_eq!(GBColor::On66.to_u8u8u8(), gpu.framebuffer[7]);
+    // OBP1 is inverted
+    assert_eq!(GBColor::On33.to_u8u8u8(), gpu.framebuffer[8]);
+    assert_eq!(GBColor::On33.to_u8u8u8(), gpu.framebuffer[159]);
+}
+
+#[test]
+fn sprite_limit_per_line() {
+    let mut gpu = sprite_gpu();
+    // 10 sprites off screen still count
+    for i in 0..10 {
+        write_sprite(&mut gpu, i, 16, 0, 1, 0x00);
+    }
+    write_sprite(&mut gpu, 10, 16, 8, 1, 0x00);
+
+    gpu.render_scanline();
+    assert_eq!(GBColor::Off.to_u8u8u8(), gpu.framebuffer[0]);
+}
+
+#[test]
+fn sprite_x_priority() {
+    let mut gpu = sprite_gpu();
+    write_sprite(&mut gpu, 0, 16, 12, 3, 0x00);
+    write_sprite(&mut gpu, 1, 16, 8, 1, 0x00);
+    // same X as sprite 1, lower priority because it comes later in OAM
+    write_sprite(&mut gpu, 2, 16, 8, 2, 0x00);
+
+    gpu.render_scanline();
+    assert_eq!(GBColor::On33.to_u8u8u8(), gpu.framebuffer[0]);
+    assert_eq!(GBColor::On33.to_u8u8u8(), gpu.framebuffer[7]);
+    assert_eq!(GBColor::On.to_u8u8u8(), gpu.framebuffer[8]);
+}
+
+#[test]
+fn sprite_8x16() {
+    let mut gpu = sprite_gpu();
+    gpu.write_register(0xFF40, 0x87);
+    // tile 3 is masked to 2 for the top half, 3 for the bottom half
+    write_sprite(&mut gpu, 0, 16, 8, 3, 0x00);
+
+    gpu.render_scanline();
+    assert_eq!(GBColor::On66.to_u8u8u8(), gpu.framebuffer[0]);
+
+    gpu.line = 8;
+    gpu.render_scanline();
+    assert_eq!(GBColor::On.to_u8u8u8(), gpu.framebuffer[8 * 160]);
+
+    // flipped vertically, the halves are swapped
+    write_sprite(&mut gpu, 0, 16, 8, 3, 0x40);
+    gpu.render_scanline();
+    assert_eq!(GBColor::On66.to_u8u8u8(), gpu.framebuffer[8 * 160]);
+}
+
+#[test]
+fn sprite_x_flip() {
+    let mut gpu = sprite_gpu();
+    // only the leftmost pixel of tile 4 is set
+    gpu.write_vram(64, 0x80);
+    write_sprite(&mut gpu, 0, 16, 8, 4, 0x00);
+    write_sprite(&mut gpu, 1, 16, 20, 4, 0x20);
+
+    gpu.render_scanline();
+    assert_eq!(GBColor::On33.to_u8u8u8(), gpu.framebuffer[0]);
+    assert_eq!(GBColor::Off.to_u8u8u8(), gpu.framebuffer[7]);
+    assert_eq!(GBColor::Off.to_u8u8u8(), gpu.framebuffer[12]);
+    assert_eq!(GBColor::On33.to_u8u8u8(), gpu.framebuffer[19]);
+}
+
+#[test]
+fn sprite_behind_background() {
+    let mut gpu = sprite_gpu();
+    write_sprite(&mut gpu, 0, 16, 8, 1, 0x80);
+    // lower priority sprite, it's still hidden where the first one is behind the background
+    write_sprite(&mut gpu, 1, 16, 8, 2, 0x00);
+    gpu.bg_line[0] = 0;
+    gpu.bg_line[1] = 2;
+
+    gpu.render_sprites();
+    assert_eq!(GBColor::On33.to_u8u8u8(), gpu.framebuffer[0]);
+    assert_eq!((0, 0, 0), gpu.framebuffer[1]);
+}
+
+#[cfg(test)]
+fn window_gpu() -> GPU {
+    let mut gpu = GPU::new();
+    // LCD, window (map at 9C00), 8000 addressing and BG on
+    gpu.write_register(0xFF40, 0xF1);
+    gpu.write_register(0xFF47, 0xE4);
+
+    // tile 1 is solid colour 3, rows 0-7 of the window use it
+    for row in 0..8 {
+        gpu.write_vram(16 + row * 2, 0xFF);
+        gpu.write_vram(16 + row * 2 + 1, 0xFF);
+    }
+    for x in 0..32 {
+        gpu.write_vram(0x1C00 + x, 1);
+    }
+    gpu
+}
+
+#[test]
+fn window_position() {
+    let mut gpu = window_gpu();
+    gpu.write_register(0xFF4A, 2);
+    gpu.write_register(0xFF4B, 7 + 10);
+    assert_eq!(2, gpu.read_register(0xFF4A));
+    assert_eq!(17, gpu.read_register(0xFF4B));
+
+    // not yet on the window lines
+    gpu.line = 1;
+    gpu.render_scanline();
+    assert_eq!(0, gpu.bg_line[10]);
+
+    gpu.line = 2;
+    gpu.render_scanline();
+    assert_eq!(0, gpu.bg_line[9]);
+    assert_eq!(3, gpu.bg_line[10]);
+    assert_eq!(3, gpu.bg_line[159]);
+    assert_eq!(1, gpu.window_line);
+}
+
+#[test]
+fn window_line_counter() {
+    let mut gpu = window_gpu();
+    gpu.write_register(0xFF4B, 7);
+
+    // 8 lines of window, then hidden for a line
+    for line in 0..8 {
+        gpu.line = line;
+        gpu.render_scanline();
+    }
+    gpu.write_register(0xFF4B, 167);
+    gpu.line = 8;
+    gpu.render_scanline();
+    assert_eq!(8, gpu.window_line);
+
+    // the window resumes from its 9th line, using the second tile row (tile 0, colour 0)
+    gpu.write_register(0xFF4B, 7);
+    gpu.line = 9;
+    gpu.render_scanline();
+    assert_eq!(0, gpu.bg_line[0]);
+    assert_eq!(9, gpu.window_line);
+}
+
+#[test]
+fn window_wx_below_7() {
+    let mut gpu = window_gpu();
+    // only the 4th pixel of tile 2 is set, with WX=4 the window starts 3 pixels to the left
+    gpu.write_vram(32, 0x10);
+    gpu.write_vram(0x1C00, 2);
+    gpu.write_register(0xFF4B, 4);
+
+    gpu.render_scanline();
+    assert_eq!(1, gpu.bg_line[0]);
+    assert_eq!(0, gpu.bg_line[1]);
+    assert_eq!(3, gpu.bg_line[5]);
+}
+
 #[test]
-fn get_tile_test() {
+fn window_disabled() {
+    let mut gpu = window_gpu();
+    gpu.write_register(0xFF40, 0xD1);
+    gpu.write_register(0xFF4B, 7);
+
+    gpu.render_scanline();
+    assert_eq!(0, gpu.bg_line[0]);
+    assert_eq!(0, gpu.window_line);
+}
+
+// every tile `n` in 8000 addressing has row `r` set to `n ^ r` in the low bitplane,
+// the tile map at 9800 holds consecutive tile indexes (row * 32 + column, wrapping at 256).
+#[cfg(test)]
+fn background_gpu() -> GPU {
     let mut gpu = GPU::new();
-    // write first line of tile #0 in set #0
-    // pixels should be: 0 1 2 3 3 2 1 0
-    gpu.write_vram(0, 0b0101_1010u8);
-    gpu.write_vram(1, 0b0011_1100u8);
+    // LCD, 8000 addressing and BG on
+    gpu.write_register(0xFF40, 0x91);
+    gpu.write_register(0xFF47, 0xE4);
 
-    let tile = gpu.get_tile(TileSelector::Set0, 0);
+    for tile in 0..256 {
+        for row in 0..8 {
+            gpu.write_vram(tile * 16 + row * 2, (tile ^ row) as u8);
+        }
+    }
+    for i in 0..0x400 {
+        gpu.write_vram(0x1800 + i, i as u8);
+    }
+    gpu
+}
 
-    assert_eq!(tile.pixels[0], GBColor::Off);
-    assert_eq!(tile.pixels[1], GBColor::On33);
-    assert_eq!(tile.pixels[2], GBColor::On66);
-    assert_eq!(tile.pixels[3], GBColor::On);
+// colour index of pixel `x` in row `row` of the fixture tile `tile`.
+#[cfg(test)]
+fn fixture_pixel(tile: usize, row: usize, x: usize) -> u8 {
+    ((tile ^ row) as u8 >> (7 - x)) & 0x01
+}
 
-    assert_eq!(tile.pixels[4], GBColor::On);
-    assert_eq!(tile.pixels[5], GBColor::On66);
-    assert_eq!(tile.pixels[6], GBColor::On33);
-    assert_eq!(tile.pixels[7], GBColor::Off);
+#[test]
+fn background_pixel_decoding() {
+    let mut gpu = background_gpu();
+    // bit 7 is the leftmost pixel, the high bitplane holds bit 1 of the colour index
+    gpu.write_vram(0, 0b0101_0011);
+    gpu.write_vram(1, 0b0011_0110);
+    gpu.line = 0;
+    gpu.render_scanline();
+
+    assert_eq!([0, 1, 2, 3, 0, 2, 3, 1], gpu.bg_line[0..8]);
+}
+
+#[test]
+fn background_no_scroll() {
+    let mut gpu = background_gpu();
+    gpu.line = 3;
+    gpu.render_scanline();
+
+    for x in 0..160 {
+        assert_eq!(fixture_pixel(x / 8, 3, x % 8), gpu.bg_line[x], "pixel {}", x);
+    }
+}
+
+#[test]
+fn background_scroll() {
+    let mut gpu = background_gpu();
+    gpu.write_register(0xFF42, 13);
+    gpu.write_register(0xFF43, 250);
+    assert_eq!(13, gpu.read_register(0xFF42));
+    assert_eq!(250, gpu.read_register(0xFF43));
+
+    gpu.line = 5;
+    gpu.render_scanline();
+
+    // map line 18 is the 3rd row of the 3rd tile row, x wraps around after 6 pixels
+    for x in 0..160 {
+        let map_x = (x + 250) % 256;
+        let tile = 2 * 32 + map_x / 8;
+        assert_eq!(fixture_pixel(tile, 2, map_x % 8), gpu.bg_line[x], "pixel {}", x);
+    }
+}
 
-    // rest of the pixels are off
-    for i in 8..64 {
-        assert_eq!(tile.pixels[i], GBColor::Off);
+#[test]
+fn background_scroll_y_wraps() {
+    let mut gpu = background_gpu();
+    gpu.write_register(0xFF42, 255);
+    gpu.line = 1;
+    gpu.render_scanline();
+
+    // line 0 of the map, again
+    for x in 0..160 {
+        assert_eq!(fixture_pixel(x / 8, 0, x % 8), gpu.bg_line[x], "pixel {}", x);
+    }
+}
+
+#[test]
+fn background_signed_addressing() {
+    let mut gpu = background_gpu();
+    // 8800 addressing, tile 0 is at 9000 and tile 0xFF (-1) at 8FF0
+    gpu.write_register(0xFF40, 0x81);
+    gpu.write_vram(0x1000, 0xF0);
+    gpu.write_vram(0x1001, 0xFF);
+    gpu.write_vram(0x1800, 0x00);
+    gpu.write_vram(0x1801, 0xFF);
+
+    gpu.render_scanline();
+    assert_eq!(3, gpu.bg_line[0]);
+    assert_eq!(2, gpu.bg_line[4]);
+    // 8FF0 is tile 0xFF in the fixture
+    assert_eq!(fixture_pixel(0xFF, 0, 0), gpu.bg_line[8]);
+    assert_eq!(fixture_pixel(0xFF, 0, 7), gpu.bg_line[15]);
+}
+
+#[test]
+fn background_tile_map_select() {
+    let mut gpu = background_gpu();
+    gpu.write_register(0xFF40, 0x99);
+    gpu.write_vram(0x1C00, 0x01);
+
+    gpu.render_scanline();
+    for x in 0..8 {
+        assert_eq!(fixture_pixel(1, 0, x), gpu.bg_line[x]);
+    }
+}
+
+#[test]
+fn background_disabled() {
+    let mut gpu = background_gpu();
+    gpu.write_register(0xFF40, 0x90);
+    // a palette where colour 0 is black, the background is still white
+    gpu.write_register(0xFF47, 0xFF);
+
+    gpu.render_scanline();
+    for x in 0..160 {
+        assert_eq!(0, gpu.bg_line[x]);
+        assert_eq!(GBColor::Off.to_u8u8u8(), gpu.framebuffer[x]);
     }
 }
 
 #[test]
-fn get_pixel_test() {
+fn vram_oam_access_locks() {
     let mut gpu = GPU::new();
-    // write first line of tile #0 in set #0
-    // pixels should be: 0 1 2 3 3 2 1 0
-    gpu.write_vram(0, 0b0101_1010u8);
-    gpu.write_vram(1, 0b0011_1100u8);
-
-    assert_eq!(gpu.get_tile_pixel(TileSelector::Set0, 0, 0), GBColor::Off);
-    assert_eq!(gpu.get_tile_pixel(TileSelector::Set0, 0, 1), GBColor::On33);
-    assert_eq!(gpu.get_tile_pixel(TileSelector::Set0, 0, 2), GBColor::On66);
-    assert_eq!(gpu.get_tile_pixel(TileSelector::Set0, 0, 3), GBColor::On);
-
-    assert_eq!(gpu.get_tile_pixel(TileSelector::Set0, 0, 4), GBColor::On);
-    assert_eq!(gpu.get_tile_pixel(TileSelector::Set0, 0, 5), GBColor::On66);
-    assert_eq!(gpu.get_tile_pixel(TileSelector::Set0, 0, 6), GBColor::On33);
-    assert_eq!(gpu.get_tile_pixel(TileSelector::Set0, 0, 7), GBColor::Off);
+    let mut interrupts = InterruptController::new();
+
+    gpu.write_vram(0, 0x11);
+    gpu.write_oam(0, 0x22);
+    gpu.write_register(0xFF40, 0x80);
+
+    // mode 2, only OAM is locked
+    gpu.write_vram(1, 0x33);
+    gpu.write_oam(1, 0x44);
+    assert_eq!(0x11, gpu.read_vram(0));
+    assert_eq!(0x33, gpu.read_vram(1));
+    assert_eq!(0xFF, gpu.read_oam(0));
+    assert_eq!(0x00, gpu.vram.oam[1]);
+
+    // mode 3, both are locked
+    gpu.emulate(80, &mut interrupts);
+    gpu.write_vram(2, 0x55);
+    gpu.write_oam(2, 0x66);
+    assert_eq!(0xFF, gpu.read_vram(0));
+    assert_eq!(0xFF, gpu.read_oam(0));
+    assert_eq!(0x00, gpu.vram.data[2]);
+    assert_eq!(0x00, gpu.vram.oam[2]);
+
+    // the locks can be disabled
+    gpu.set_access_locks(false);
+    gpu.write_vram(2, 0x55);
+    gpu.write_oam(2, 0x66);
+    assert_eq!(0x55, gpu.read_vram(2));
+    assert_eq!(0x66, gpu.read_oam(2));
+    gpu.set_access_locks(true);
+
+    // HBlank, both are free
+    gpu.emulate(172, &mut interrupts);
+    assert_eq!(0x11, gpu.read_vram(0));
+    assert_eq!(0x22, gpu.read_oam(0));
+
+    // the OAM DMA is not affected
+    gpu.emulate(456 - 252, &mut interrupts);
+    gpu.dma_write_oam(3, 0x77);
+    assert_eq!(0x77, gpu.vram.oam[3]);
 }
@@ -1,2 +1,4 @@
 pub mod gpu;
-mod data;
\ No newline at end of file
+pub mod renderer;
+pub mod fifo;
+mod data;
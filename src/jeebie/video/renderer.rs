@@ -0,0 +1,42 @@
+use super::gpu::{GPU, PIXEL_TRANSFER_CYCLES};
+
+/// Draws the visible lines during mode 3 (pixel transfer).
+/// The GPU handles the timing of the other modes and lets the renderer decide when mode 3 ends,
+/// HBlank takes the rest of the line.
+pub trait Renderer {
+    /// Called when mode 3 starts, right after the OAM search of the line.
+    fn start_line(&mut self, gpu: &mut GPU);
+
+    /// Advances mode 3 by one cycle. Returns true when the line is done and HBlank starts.
+    fn tick(&mut self, gpu: &mut GPU) -> bool;
+}
+
+/// Draws the whole line at once, at the end of a fixed length mode 3.
+/// This is cheap, but registers written during mode 3 only take effect on the next line.
+pub struct ScanlineRenderer {
+    cycles: u32,
+}
+
+impl ScanlineRenderer {
+    pub fn new() -> ScanlineRenderer {
+        ScanlineRenderer { cycles: 0 }
+    }
+}
+
+impl Renderer for ScanlineRenderer {
+    fn start_line(&mut self, _gpu: &mut GPU) {
+        self.cycles = 0;
+    }
+
+    fn tick(&mut self, gpu: &mut GPU) -> bool {
+        self.cycles += 1;
+
+        if self.cycles < PIXEL_TRANSFER_CYCLES {
+            return false;
+        }
+
+        // scanline is done, write it to framebuffer
+        gpu.render_scanline();
+        true
+    }
+}
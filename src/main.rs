@@ -3,24 +3,81 @@ extern crate sdl2;
 mod jeebie;
 
 use jeebie::core::cpu::CPU;
+use jeebie::joypad::Button;
+use jeebie::bootrom::{BootRom, Model};
+use jeebie::video::fifo::PixelFifo;
 
 use std::env;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::error::Error;
 
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
 use sdl2::render::{Canvas, Texture};
+use sdl2::video::Window;
+use sdl2::{EventPump, Sdl};
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 
+/// Battery backed RAM is flushed to disk every this many frames (about 5 seconds),
+/// so that progress is not lost if the emulator doesn't exit cleanly.
+const SAVE_INTERVAL_FRAMES: u32 = 300;
+
+/// Sample rate requested to the audio device, the one it actually uses is given to the APU.
+const SAMPLE_RATE: i32 = 48000;
+
+/// Amount of audio kept in the queue, in seconds. The emulation is paced by the audio device,
+/// waiting when the queue holds more than twice this amount.
+const AUDIO_LATENCY: f64 = 0.05;
+
+/// Largest change to the rate of the samples, used to keep the queue close to `AUDIO_LATENCY`
+/// without audible pitch changes.
+const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+
+/// Duration of a frame (70224 cycles at 4194304 Hz, about 59.7 Hz), used to pace the emulation
+/// when there's no audio device.
+const FRAME_DURATION_NANOS: u64 = 16_742_706;
+
+/// Usage: jeebie <rom> [--skip-boot[=MODEL]] [--boot-rom=PATH] [--pixel-fifo]
+/// With --skip-boot, the boot ROM is not run and the cartridge starts right away in the state
+/// left by the boot ROM of MODEL (one of dmg0, dmg, mgb, cgb, agb), DMG by default.
+/// With --boot-rom, the boot ROM image at PATH is run instead of the built-in DMG one.
+/// With --pixel-fifo, lines are drawn pixel by pixel, showing the effects of registers written
+/// in the middle of a line. This is slower than the default scanline renderer.
 fn main() {
     let args: Vec<String> = env::args().collect();
-    run_emulator(&args[1]).expect("An error occurred when running the emulator");
+
+    let mut skip_boot = None;
+    let mut boot_rom = None;
+    let mut pixel_fifo = false;
+    for arg in &args[2..] {
+        if arg.starts_with("--boot-rom=") {
+            boot_rom = Some(arg["--boot-rom=".len()..].to_string());
+        } else if arg == "--skip-boot" {
+            skip_boot = Some(Model::DMG);
+        } else if arg.starts_with("--skip-boot=") {
+            let model = arg["--skip-boot=".len()..].parse().expect("Invalid --skip-boot model");
+            skip_boot = Some(model);
+        } else if arg == "--pixel-fifo" {
+            pixel_fifo = true;
+        }
+    }
+
+    run_emulator(&args[1], skip_boot, boot_rom, pixel_fifo).expect("An error occurred when running the emulator");
 }
 
-pub fn run_emulator(path: &str) -> Result<(), Box<dyn Error>> {
+pub fn run_emulator(path: &str, skip_boot: Option<Model>, boot_rom: Option<String>, pixel_fifo: bool) -> Result<(), Box<dyn Error>> {
     let mut emulator = CPU::new_with_path(path)?;
+    if let Some(boot_rom_path) = boot_rom {
+        emulator.set_boot_rom(BootRom::new_with_path(&boot_rom_path)?);
+    }
+    if let Some(model) = skip_boot {
+        emulator.skip_boot(model);
+    }
+    if pixel_fifo {
+        emulator.mem.gpu.set_renderer(Box::new(PixelFifo::new()));
+    }
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
     let (width, height) = (160, 144);
@@ -31,6 +88,19 @@ pub fn run_emulator(path: &str) -> Result<(), Box<dyn Error>> {
         .opengl()
         .build()?;
 
+    // without an audio device the emulator still runs, paced by a timer
+    let audio_queue = match open_audio(&sdl_context) {
+        Ok(queue) => {
+            emulator.mem.apu.set_sample_rate(queue.spec().freq as u32);
+            queue.resume();
+            Some(queue)
+        },
+        Err(e) => {
+            eprintln!("Audio disabled: {}", e);
+            None
+        },
+    };
+
     let mut event_pump = sdl_context.event_pump()?;
     let mut canvas = window.into_canvas()
                     .present_vsync()
@@ -39,12 +109,37 @@ pub fn run_emulator(path: &str) -> Result<(), Box<dyn Error>> {
 
     let tc = canvas.texture_creator();
     let mut texture = tc.create_texture_streaming(PixelFormatEnum::RGB24, width, height)?;
-    
-    'running: loop {
+
+    let result = run_loop(&mut emulator, &mut event_pump, &mut canvas, &mut texture, audio_queue.as_ref());
+
+    // the battery is saved even if the loop stopped because of an error
+    let saved = emulator.save_battery();
+    result?;
+    saved
+}
+
+/// Runs the emulator one frame at a time until the window is closed, or an error occurs.
+/// Without `audio_queue`, frames are paced by sleeping until the next one is due.
+fn run_loop(emulator: &mut CPU, event_pump: &mut EventPump, canvas: &mut Canvas<Window>, texture: &mut Texture, audio_queue: Option<&AudioQueue<f32>>) -> Result<(), Box<dyn Error>> {
+    let mut samples = vec![];
+    let mut frames_since_save = 0;
+    let mut next_frame = Instant::now();
+
+    loop {
         // Handle inputs
         for event in event_pump.poll_iter() {
             match event {
-                Event::Quit {..} | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => break 'running,
+                Event::Quit {..} | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return Ok(()),
+                Event::KeyDown { keycode: Some(key), repeat: false, .. } => {
+                    if let Some(button) = key_to_button(key) {
+                        emulator.press(button);
+                    }
+                },
+                Event::KeyUp { keycode: Some(key), .. } => {
+                    if let Some(button) = key_to_button(key) {
+                        emulator.release(button);
+                    }
+                },
                 _ => {},
             };
         }
@@ -53,15 +148,78 @@ pub fn run_emulator(path: &str) -> Result<(), Box<dyn Error>> {
         let fb = emulator.exec_one_frame();
 
         // Draw
-        draw_step(&mut canvas, &mut texture, &fb)?;
+        draw_step(canvas, texture, fb)?;
+
+        // Play
+        if let Some(audio_queue) = audio_queue {
+            emulator.mem.apu.drain_samples(&mut samples);
+            if !audio_queue.queue(&samples) {
+                return Err(sdl2::get_error().into());
+            }
+            samples.clear();
+            sync_audio(audio_queue, emulator);
+        } else {
+            next_frame += Duration::from_nanos(FRAME_DURATION_NANOS);
+            let now = Instant::now();
+            if next_frame > now {
+                thread::sleep(next_frame - now);
+            } else {
+                // too slow to keep up, don't try to catch up later
+                next_frame = now;
+            }
+        }
 
-        thread::sleep(Duration::from_millis(16));
+        frames_since_save += 1;
+        if frames_since_save == SAVE_INTERVAL_FRAMES {
+            emulator.save_battery()?;
+            frames_since_save = 0;
+        }
     }
+}
 
-    Ok(())
+/// Opens a stereo audio queue, at the sample rate closest to `SAMPLE_RATE`.
+fn open_audio(sdl_context: &Sdl) -> Result<AudioQueue<f32>, String> {
+    let audio_subsystem = sdl_context.audio()?;
+    let desired_spec = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE),
+        channels: Some(2),
+        samples: Some(1024),
+    };
+    audio_subsystem.open_queue(None, &desired_spec)
+}
+
+/// Dynamic rate control: the samples are produced slightly faster when the audio queue runs low
+/// and slightly slower when it fills up, so that it stays close to the target latency.
+/// If the emulation still gets too far ahead, it waits for the queue to be played.
+fn sync_audio(queue: &AudioQueue<f32>, emulator: &mut CPU) {
+    let target = queue.spec().freq as f64 * AUDIO_LATENCY;
+    // each stereo sample takes 8 bytes
+    let queued = |queue: &AudioQueue<f32>| (queue.size() / 8) as f64;
+
+    let error = ((target - queued(queue)) / target).clamp(-1.0, 1.0);
+    emulator.mem.apu.set_rate_adjustment(1.0 + MAX_RATE_ADJUSTMENT * error);
+
+    while queued(queue) > target * 2.0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// Default keyboard bindings for the joypad.
+fn key_to_button(key: Keycode) -> Option<Button> {
+    match key {
+        Keycode::Up => Some(Button::Up),
+        Keycode::Down => Some(Button::Down),
+        Keycode::Left => Some(Button::Left),
+        Keycode::Right => Some(Button::Right),
+        Keycode::Z => Some(Button::A),
+        Keycode::X => Some(Button::B),
+        Keycode::Return => Some(Button::Start),
+        Keycode::Backspace => Some(Button::Select),
+        _ => None,
+    }
 }
 
-fn draw_step(canvas: &mut Canvas<sdl2::video::Window>, texture: &mut Texture, framebuffer: &[(u8, u8, u8)]) -> Result<(), Box<dyn Error>> {
+fn draw_step(canvas: &mut Canvas<Window>, texture: &mut Texture, framebuffer: &[(u8, u8, u8)]) -> Result<(), Box<dyn Error>> {
     canvas.clear();
 
     let (width, height) = (160, 144);
@@ -71,7 +229,7 @@ fn draw_step(canvas: &mut Canvas<sdl2::video::Window>, texture: &mut Texture, fr
         for y in 0..height {
             for x in 0..width {
                 let offset = x * 3 + pitch * y;
-                let fb_index = x + (y * height);
+                let fb_index = x + y * width;
                 let (r, g, b) = framebuffer[fb_index];
 
                 buffer[offset] = r;